@@ -0,0 +1,144 @@
+//! The replicated state machines run on top of the protocol.
+//!
+//! Operations and replies are opaque to `System`, which only orders
+//! them; each application defines its own encoding, with helpers for
+//! the clients to build its operations and read its replies.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use serde::{Serialize, Deserialize};
+use tokio::io;
+
+/// Which application the replicas run.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum AppKind {
+    /// A single counter, incremented by each operation.
+    #[default]
+    Counter,
+    /// A key-value store, like the `app-scaling-tests` microbenchmark.
+    Kv,
+}
+
+/// A deterministic state machine, executing the operations decided
+/// by the protocol.
+pub trait Application: Debug + Send {
+    /// Apply an operation, in the order it was decided, and return
+    /// the reply for the client. Replicas must produce the same reply
+    /// from the same state, even for operations they can't decode.
+    fn execute(&mut self, op: &[u8]) -> Vec<u8>;
+
+    /// Serialize the whole state.
+    fn snapshot(&self) -> Vec<u8>;
+
+    /// Replace the state with one returned by `snapshot`.
+    fn restore(&mut self, snapshot: &[u8]) -> io::Result<()>;
+}
+
+pub fn new(kind: AppKind) -> Box<dyn Application> {
+    match kind {
+        AppKind::Counter => Box::<Counter>::default(),
+        AppKind::Kv => Box::<KeyValue>::default(),
+    }
+}
+
+/// Adds the `i32` of each operation to a running total, and
+/// replies with the new total as an `i64`.
+#[derive(Debug, Default)]
+pub struct Counter {
+    total: i64,
+}
+
+impl Counter {
+    pub fn op(value: i32) -> Vec<u8> {
+        bincode::serialize(&value).unwrap()
+    }
+
+    pub fn reply(reply: &[u8]) -> Option<i64> {
+        bincode::deserialize(reply).ok()
+    }
+}
+
+impl Application for Counter {
+    fn execute(&mut self, op: &[u8]) -> Vec<u8> {
+        // garbage leaves the total alone
+        if let Ok(value) = bincode::deserialize::<i32>(op) {
+            self.total = self.total.wrapping_add(value as i64);
+        }
+        bincode::serialize(&self.total).unwrap()
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        bincode::serialize(&self.total).unwrap()
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> io::Result<()> {
+        self.total = bincode::deserialize(snapshot).map_err(invalid_data)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KvRequest {
+    Read(String),
+    Write(String, String),
+    Delete(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KvReply {
+    None,
+    Value(String),
+    // the operation couldn't be decoded
+    Invalid,
+}
+
+/// A map from strings to strings. Writes and deletes reply with
+/// the previous value of the key, reads with its current one.
+#[derive(Debug, Default)]
+pub struct KeyValue {
+    // ordered, so that snapshots of the same state are identical
+    inner: BTreeMap<String, String>,
+}
+
+impl KeyValue {
+    pub fn op(request: &KvRequest) -> Vec<u8> {
+        bincode::serialize(request).unwrap()
+    }
+
+    pub fn reply(reply: &[u8]) -> Option<KvReply> {
+        bincode::deserialize(reply).ok()
+    }
+}
+
+impl Application for KeyValue {
+    fn execute(&mut self, op: &[u8]) -> Vec<u8> {
+        let reply = match bincode::deserialize(op) {
+            Ok(KvRequest::Read(key)) => self.inner.get(&key).cloned().into(),
+            Ok(KvRequest::Write(key, value)) => self.inner.insert(key, value).into(),
+            Ok(KvRequest::Delete(key)) => self.inner.remove(&key).into(),
+            Err(_) => KvReply::Invalid,
+        };
+        bincode::serialize(&reply).unwrap()
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        bincode::serialize(&self.inner).unwrap()
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> io::Result<()> {
+        self.inner = bincode::deserialize(snapshot).map_err(invalid_data)?;
+        Ok(())
+    }
+}
+
+impl From<Option<String>> for KvReply {
+    fn from(value: Option<String>) -> Self {
+        value.map_or(KvReply::None, KvReply::Value)
+    }
+}
+
+fn invalid_data(e: bincode::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
@@ -0,0 +1,210 @@
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+
+use ring::{hmac, rand, signature};
+use ring::rand::SecureRandom;
+use ring::signature::KeyPair;
+use serde::Deserialize;
+use tokio::io;
+
+/// Size of an ed25519 signature.
+const SIGNATURE_LEN: usize = 64;
+
+/// Size of the HMAC key each pair of replicas shares.
+const HMAC_KEY_LEN: usize = 32;
+
+/// How replicas authenticate the messages they send to each other.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthKind {
+    /// Trust whoever is on the other end of a connection.
+    #[default]
+    None,
+    /// A vector with one HMAC-SHA256 per replica, with a key
+    /// file per pair of replicas.
+    Hmac,
+    /// An ed25519 signature, with a key file per replica.
+    Ed25519,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AuthConfig {
+    pub kind: AuthKind,
+    /// Holds `r<id>.pk8` and `r<id>.pub` for each replica, and
+    /// `r<i>-r<j>.key` for each pair of replicas with `i <= j`,
+    /// as written by the `keygen` binary. A replica only reads
+    /// its own files.
+    pub key_dir: PathBuf,
+}
+
+/// Computes and checks the tag appended to every frame a replica
+/// sends to another replica.
+pub trait Authenticator: Debug + Send + Sync {
+    /// Size of the tag, in bytes.
+    fn tag_len(&self) -> usize;
+
+    /// Tag `payload` for every other replica.
+    fn tag(&self, payload: &[u8]) -> Vec<u8>;
+
+    /// Check the tag replica `from` computed over `payload`.
+    fn verify(&self, from: u32, payload: &[u8], tag: &[u8]) -> bool;
+}
+
+/// Build the authenticator of replica `id`, in a cluster of `n`.
+pub fn new(cfg: &AuthConfig, id: u32, n: u32) -> io::Result<Box<dyn Authenticator>> {
+    match cfg.kind {
+        AuthKind::None => Ok(Box::new(NoAuth)),
+        AuthKind::Hmac => Ok(Box::new(HmacVector::load(&cfg.key_dir, id, n)?)),
+        AuthKind::Ed25519 => Ok(Box::new(Ed25519::load(&cfg.key_dir, id, n)?)),
+    }
+}
+
+#[derive(Debug)]
+struct NoAuth;
+
+impl Authenticator for NoAuth {
+    fn tag_len(&self) -> usize {
+        0
+    }
+
+    fn tag(&self, _payload: &[u8]) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn verify(&self, _from: u32, _payload: &[u8], _tag: &[u8]) -> bool {
+        true
+    }
+}
+
+/// An authenticator in the style of PBFT: a broadcast frame carries
+/// a MAC for each replica, so it is computed once for all of them.
+#[derive(Debug)]
+struct HmacVector {
+    id: u32,
+    // the key we share with each replica
+    keys: Vec<hmac::Key>,
+}
+
+impl HmacVector {
+    // only the two replicas of a pair hold its key, so
+    // neither can be impersonated by a third one
+    fn load(dir: &Path, id: u32, n: u32) -> io::Result<Self> {
+        let keys = (0..n)
+            .map(|other| {
+                let path = dir.join(pair_key_file(id, other));
+                let key = std::fs::read(&path)?;
+                if key.len() < HMAC_KEY_LEN {
+                    let e = format!("{} is shorter than {} bytes", path.display(), HMAC_KEY_LEN);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+                }
+                Ok(key)
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(HmacVector::new(id, keys))
+    }
+
+    fn new(id: u32, keys: Vec<Vec<u8>>) -> Self {
+        let keys = keys
+            .iter()
+            .map(|key| hmac::Key::new(hmac::HMAC_SHA256, key))
+            .collect();
+        HmacVector { id, keys }
+    }
+}
+
+impl Authenticator for HmacVector {
+    fn tag_len(&self) -> usize {
+        self.keys.len() * 32
+    }
+
+    fn tag(&self, payload: &[u8]) -> Vec<u8> {
+        let mut tag = Vec::with_capacity(self.tag_len());
+        for key in self.keys.iter() {
+            tag.extend_from_slice(hmac::sign(key, payload).as_ref());
+        }
+        tag
+    }
+
+    fn verify(&self, from: u32, payload: &[u8], tag: &[u8]) -> bool {
+        let key = match self.keys.get(from as usize) {
+            Some(key) => key,
+            None => return false,
+        };
+        let i = self.id as usize * 32;
+        match tag.get(i..i + 32) {
+            Some(mac) => hmac::verify(key, payload, mac).is_ok(),
+            None => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Ed25519 {
+    key_pair: signature::Ed25519KeyPair,
+    public_keys: Vec<signature::UnparsedPublicKey<Vec<u8>>>,
+}
+
+impl Ed25519 {
+    fn load(dir: &Path, id: u32, n: u32) -> io::Result<Self> {
+        let pkcs8 = std::fs::read(dir.join(format!("r{}.pk8", id)))?;
+        let key_pair = signature::Ed25519KeyPair::from_pkcs8(&pkcs8)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        let public_keys = (0..n)
+            .map(|i| {
+                let bytes = std::fs::read(dir.join(format!("r{}.pub", i)))?;
+                Ok(signature::UnparsedPublicKey::new(&signature::ED25519, bytes))
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(Ed25519 { key_pair, public_keys })
+    }
+}
+
+impl Authenticator for Ed25519 {
+    fn tag_len(&self) -> usize {
+        SIGNATURE_LEN
+    }
+
+    fn tag(&self, payload: &[u8]) -> Vec<u8> {
+        self.key_pair.sign(payload).as_ref().to_vec()
+    }
+
+    fn verify(&self, from: u32, payload: &[u8], tag: &[u8]) -> bool {
+        self.public_keys
+            .get(from as usize)
+            .is_some_and(|pk| pk.verify(payload, tag).is_ok())
+    }
+}
+
+/// The HMAC authenticator of replica `id`, given the key it
+/// shares with each replica, in order; for the simulator, which
+/// hands out the keys itself.
+pub fn hmac_vector(id: u32, keys: Vec<Vec<u8>>) -> Box<dyn Authenticator> {
+    Box::new(HmacVector::new(id, keys))
+}
+
+// the file holding the key replicas `a` and `b` share
+fn pair_key_file(a: u32, b: u32) -> String {
+    format!("r{}-r{}.key", a.min(b), a.max(b))
+}
+
+/// Write a new ed25519 key pair for each of `n` replicas to `dir`,
+/// and a new HMAC key for each pair of them.
+pub fn generate_keys(dir: &Path, n: u32) -> io::Result<()> {
+    let rng = rand::SystemRandom::new();
+    std::fs::create_dir_all(dir)?;
+    for i in 0..n {
+        let pkcs8 = signature::Ed25519KeyPair::generate_pkcs8(&rng)
+            .map_err(|_| io::Error::other("failed to generate a key pair"))?;
+        let key_pair = signature::Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        std::fs::write(dir.join(format!("r{}.pk8", i)), pkcs8.as_ref())?;
+        std::fs::write(dir.join(format!("r{}.pub", i)), key_pair.public_key().as_ref())?;
+        for j in i..n {
+            let mut key = [0; HMAC_KEY_LEN];
+            rng.fill(&mut key)
+                .map_err(|_| io::Error::other("failed to generate a key"))?;
+            std::fs::write(dir.join(pair_key_file(i, j)), key)?;
+        }
+    }
+    Ok(())
+}
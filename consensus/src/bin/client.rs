@@ -0,0 +1,105 @@
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use tokio::io;
+
+use consensus::app::{AppKind, Counter, KeyValue, KvRequest};
+use consensus::client::{Client, RETRANSMIT};
+use consensus::config::ClusterArgs;
+use consensus::metrics::percentile;
+use consensus::util::Prng;
+
+/// A closed loop client, reporting throughput and latency.
+#[derive(Debug, Parser)]
+struct Args {
+    /// Our client's id.
+    #[arg(long, env = "ID")]
+    id: u32,
+    /// Number of requests to submit.
+    #[arg(long, env = "OPS", default_value_t = 10000)]
+    ops: usize,
+    /// Keys picked from by the kv workload.
+    #[arg(long, default_value_t = 128000)]
+    keys: u32,
+    /// Fraction of kv operations that are reads; the rest are writes.
+    #[arg(long, default_value_t = 0.66)]
+    reads: f64,
+    /// Size of the values written by the kv workload.
+    #[arg(long, default_value_t = 64)]
+    value_size: usize,
+    /// Milliseconds to wait for replies before sending a request again.
+    #[arg(long, default_value_t = RETRANSMIT.as_millis() as u64)]
+    retransmit_ms: u64,
+    #[command(flatten)]
+    cluster: ClusterArgs,
+}
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let args = Args::parse();
+    let (id, ops) = (args.id, args.ops);
+    let cluster = args.cluster.load()?;
+    let mut client = Client::connect(id, cluster.f, &cluster.clients).await?;
+    client.retransmit_after(Duration::from_millis(args.retransmit_ms));
+    let mut workload = Workload::new(&args, cluster.app);
+
+    let mut latencies = Vec::with_capacity(ops);
+    let start = Instant::now();
+    for i in 0..ops {
+        let before = Instant::now();
+        client.invoke(workload.next_op(i)).await?;
+        latencies.push(before.elapsed());
+    }
+    let elapsed = start.elapsed();
+
+    latencies.sort_unstable();
+    println!(
+        "Client c{} finished {} ops in {:?} ({:.2} ops/s), latency p50 {:?} p99 {:?} max {:?}",
+        id,
+        ops,
+        elapsed,
+        ops as f64 / elapsed.as_secs_f64(),
+        percentile(&latencies, 0.50),
+        percentile(&latencies, 0.99),
+        latencies.last().copied().unwrap_or_default(),
+    );
+
+    Ok(())
+}
+
+/// Operations for the replicas' application. The kv mix follows the
+/// one of `app-scaling-tests`: uniformly picked keys, mostly reads.
+struct Workload {
+    app: AppKind,
+    prng: Prng,
+    keys: u32,
+    reads: f64,
+    value: String,
+}
+
+impl Workload {
+    fn new(args: &Args, app: AppKind) -> Self {
+        Workload {
+            app,
+            prng: Prng::new(args.id as u64),
+            keys: args.keys.max(1),
+            reads: args.reads,
+            value: "x".repeat(args.value_size),
+        }
+    }
+
+    fn next_op(&mut self, i: usize) -> Vec<u8> {
+        match self.app {
+            AppKind::Counter => Counter::op(i as i32),
+            AppKind::Kv => {
+                let key = format!("key{}", self.prng.next().unwrap() % self.keys);
+                let request = if self.prng.unit() < self.reads {
+                    KvRequest::Read(key)
+                } else {
+                    KvRequest::Write(key, self.value.clone())
+                };
+                KeyValue::op(&request)
+            },
+        }
+    }
+}
@@ -0,0 +1,25 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use tokio::io;
+
+use consensus::auth;
+
+/// Generate the ed25519 key files of every replica, and
+/// the HMAC key file of every pair of replicas.
+#[derive(Debug, Parser)]
+struct Args {
+    /// Number of replicas.
+    #[arg(long, default_value_t = 4)]
+    n: u32,
+    /// Where to write the key files.
+    #[arg(long, default_value = "keys")]
+    key_dir: PathBuf,
+}
+
+fn main() -> io::Result<()> {
+    let args = Args::parse();
+    auth::generate_keys(&args.key_dir, args.n)?;
+    println!("Wrote the keys of {} replicas to {}", args.n, args.key_dir.display());
+    Ok(())
+}
@@ -0,0 +1,149 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::log::Digest;
+use crate::message::Signed;
+
+/// Proof that a replica proposed two different values
+/// for the same sequence number.
+#[derive(Debug, Copy, Clone)]
+pub struct Equivocation {
+    pub seq: i32,
+    pub from: u32,
+    pub first: Digest,
+    pub second: Digest,
+}
+
+/// The reason why a consensus message was not accounted for.
+#[derive(Debug, Copy, Clone)]
+pub enum Rejected {
+    /// The sender has already voted in this phase.
+    Duplicate,
+    /// The vote is not for the value we have accepted.
+    Mismatch,
+    /// The leader proposed a different value before.
+    Equivocation(Equivocation),
+}
+
+#[derive(Debug, Default)]
+struct Instance {
+    value: Option<Digest>,
+    // votes may arrive before the proposal they are for
+    prepares: HashMap<u32, Digest>,
+    commits: HashMap<u32, Digest>,
+    // the frame of each prepare vote, to prove it to others
+    frames: BTreeMap<u32, Signed>,
+}
+
+/// Keeps track of who voted for what, on each consensus instance.
+#[derive(Debug, Default)]
+pub struct Certificates {
+    instances: HashMap<i32, Instance>,
+    evidence: Vec<Equivocation>,
+}
+
+impl Certificates {
+    /// Accept the value proposed by the leader, who then votes
+    /// for it with a `Prepare` of its own, like everyone else.
+    pub fn pre_prepare(&mut self, seq: i32, from: u32, value: Digest) -> Result<(), Rejected> {
+        let instance = self.instances.entry(seq).or_default();
+        match instance.value {
+            None => {
+                instance.value = Some(value);
+                Ok(())
+            },
+            Some(first) if first == value => Err(Rejected::Duplicate),
+            Some(first) => {
+                let e = Equivocation { seq, from, first, second: value };
+                self.evidence.push(e);
+                Err(Rejected::Equivocation(e))
+            },
+        }
+    }
+
+    /// Account for a `Prepare` vote, which arrived in `frame`.
+    pub fn prepare(&mut self, seq: i32, from: u32, value: Digest, frame: Signed) -> Result<(), Rejected> {
+        let instance = self.instances.entry(seq).or_default();
+        let voted = vote(instance.value, &mut instance.prepares, from, value);
+        if !matches!(voted, Err(Rejected::Duplicate)) {
+            instance.frames.insert(from, frame);
+        }
+        voted
+    }
+
+    pub fn commit(&mut self, seq: i32, from: u32, value: Digest) -> Result<(), Rejected> {
+        let instance = self.instances.entry(seq).or_default();
+        vote(instance.value, &mut instance.commits, from, value)
+    }
+
+    /// Number of distinct `Prepare` votes for the accepted value.
+    pub fn prepares(&self, seq: i32) -> u32 {
+        self.instances.get(&seq).map_or(0, |i| count(i.value, &i.prepares))
+    }
+
+    /// Number of distinct `Commit` votes for the accepted value.
+    pub fn commits(&self, seq: i32) -> u32 {
+        self.instances.get(&seq).map_or(0, |i| count(i.value, &i.commits))
+    }
+
+    /// The frames of the `Prepare` votes for the accepted
+    /// value, ordered by voter.
+    pub fn prepare_proof(&self, seq: i32) -> Vec<Signed> {
+        let instance = match self.instances.get(&seq) {
+            Some(instance) => instance,
+            None => return Vec::new(),
+        };
+        instance.frames
+            .iter()
+            .filter(|&(from, _)| instance.prepares.get(from).copied() == instance.value)
+            .map(|(_, frame)| frame.clone())
+            .collect()
+    }
+
+    /// The value `quorum` replicas committed on `seq`, whether
+    /// or not we have accepted it ourselves.
+    pub fn decided(&self, seq: i32, quorum: u32) -> Option<Digest> {
+        let instance = self.instances.get(&seq)?;
+        let mut votes: HashMap<Digest, u32> = HashMap::new();
+        for value in instance.commits.values() {
+            *votes.entry(*value).or_default() += 1;
+        }
+        votes
+            .into_iter()
+            .find(|&(_, votes)| votes >= quorum)
+            .map(|(value, _)| value)
+    }
+
+    /// All the equivocations detected so far.
+    pub fn evidence(&self) -> &[Equivocation] {
+        &self.evidence
+    }
+
+    /// Forget the votes of instances older than `seq`.
+    pub fn discard_below(&mut self, seq: i32) {
+        self.instances.retain(|&s, _| s >= seq);
+    }
+
+    /// Forget the votes of instances from `seq` onwards, which
+    /// will be decided again in a new view.
+    pub fn discard_from(&mut self, seq: i32) {
+        self.instances.retain(|&s, _| s < seq);
+    }
+}
+
+fn vote(accepted: Option<Digest>, voters: &mut HashMap<u32, Digest>, from: u32, value: Digest) -> Result<(), Rejected> {
+    if voters.contains_key(&from) {
+        return Err(Rejected::Duplicate);
+    }
+    voters.insert(from, value);
+    match accepted {
+        Some(accepted) if accepted != value => Err(Rejected::Mismatch),
+        _ => Ok(()),
+    }
+}
+
+fn count(accepted: Option<Digest>, voters: &HashMap<u32, Digest>) -> u32 {
+    match accepted {
+        Some(accepted) => voters.values().filter(|&&v| v == accepted).count() as u32,
+        None => 0,
+    }
+}
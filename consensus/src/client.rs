@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::io;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::message::{ReplyMessage, RequestMessage};
+use crate::node::{read_frame, write_frame};
+
+/// How long a client waits for replies before sending its
+/// request again, unless told otherwise.
+pub const RETRANSMIT: Duration = Duration::from_secs(1);
+
+/// A client connected to every reachable replica. Requests are
+/// sent to all of them, and complete once `f + 1` replicas reply
+/// with the same result.
+#[derive(Debug)]
+pub struct Client {
+    id: u32,
+    f: u32,
+    next_id: u32,
+    retransmit: Duration,
+    replicas: Vec<Option<OwnedWriteHalf>>,
+    replies: mpsc::Receiver<(usize, ReplyMessage)>,
+}
+
+impl Client {
+    /// Connect to the replicas' client endpoints. Up to `f`
+    /// replicas may be unreachable.
+    pub async fn connect(id: u32, f: u32, addrs: &[SocketAddr]) -> io::Result<Self> {
+        let (tx, rx) = mpsc::channel(128);
+        let mut replicas = Vec::with_capacity(addrs.len());
+        for (i, &addr) in addrs.iter().enumerate() {
+            let conn = match TcpStream::connect(addr).await {
+                Ok(conn) => conn,
+                Err(_) => {
+                    replicas.push(None);
+                    continue;
+                },
+            };
+            conn.set_nodelay(true)?;
+            let (mut rx_half, mut tx_half) = conn.into_split();
+            tx_half.write_u32(id).await?;
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                loop {
+                    let m = match read_frame(&mut rx_half).await {
+                        Ok(m) => m,
+                        Err(e) if e.kind() == io::ErrorKind::InvalidData => continue,
+                        Err(_) => return,
+                    };
+                    if tx.send((i, m)).await.is_err() {
+                        return;
+                    }
+                }
+            });
+            replicas.push(Some(tx_half));
+        }
+        let connected = replicas.iter().filter(|r| r.is_some()).count();
+        if connected < (2*f as usize + 1) {
+            return Err(io::Error::other("not enough replicas reachable"));
+        }
+        Ok(Client { id, f, next_id: 0, retransmit: RETRANSMIT, replicas, replies: rx })
+    }
+
+    /// Send requests again after waiting `timeout` for their replies.
+    pub fn retransmit_after(&mut self, timeout: Duration) {
+        self.retransmit = timeout;
+    }
+
+    /// Submit an operation, encoded for the replicas' application,
+    /// and wait for its reply.
+    pub async fn invoke(&mut self, op: Vec<u8>) -> io::Result<ReplyMessage> {
+        let request = RequestMessage {
+            client: self.id,
+            id: self.next_id,
+            op,
+        };
+        self.next_id += 1;
+        self.send(&request).await;
+
+        // replicas that agree on each reply
+        let mut votes: HashMap<(i32, Vec<u8>), Vec<usize>> = HashMap::new();
+        let mut deadline = Instant::now() + self.retransmit;
+        loop {
+            let received = tokio::time::timeout_at(deadline, self.replies.recv()).await;
+            let (from, reply) = match received {
+                Ok(reply) => reply.ok_or_else(|| io::Error::other("all replicas disconnected"))?,
+                // the request or the replies may have been lost, or the
+                // leader ignored it; replicas that executed it already
+                // only send their reply again
+                Err(_) => {
+                    self.send(&request).await;
+                    deadline = Instant::now() + self.retransmit;
+                    continue;
+                },
+            };
+            if reply.client != self.id || reply.id != request.id {
+                // late reply to an old request
+                continue;
+            }
+            let voters = votes.entry((reply.seq, reply.result.clone())).or_default();
+            if voters.contains(&from) {
+                continue;
+            }
+            voters.push(from);
+            if voters.len() as u32 > self.f {
+                return Ok(reply);
+            }
+        }
+    }
+
+    async fn send(&mut self, request: &RequestMessage) {
+        for replica in self.replicas.iter_mut() {
+            let failed = match replica {
+                Some(conn) => write_frame(conn, request).await.is_err(),
+                None => false,
+            };
+            // the replica may have crashed
+            if failed {
+                *replica = None;
+            }
+        }
+    }
+}
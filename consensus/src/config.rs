@@ -0,0 +1,208 @@
+use std::convert::TryFrom;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::io;
+
+use crate::app::AppKind;
+use crate::auth::{AuthConfig, AuthKind};
+use crate::fault::Fault;
+use crate::metrics::MetricsConfig;
+use crate::wal::WalConfig;
+
+/// Replica `i` listens on `base_port + i` in localhost mode, and
+/// accepts clients on `base_port + CLIENT_PORT_OFFSET + i`.
+pub const CLIENT_PORT_OFFSET: u16 = 1000;
+
+#[derive(Debug)]
+pub struct Config {
+    pub f: u32,
+    pub id: u32,
+    pub addrs: Vec<SocketAddr>,
+    pub listen: SocketAddr,
+    pub client_listen: SocketAddr,
+    pub timeout: Duration,
+    pub checkpoint_period: i32,
+    pub batch_size: usize,
+    pub batch_timeout: Duration,
+    /// Most consensus instances the leader runs at once.
+    pub window: i32,
+    pub auth: AuthConfig,
+    pub app: AppKind,
+    /// Where decisions are persisted, if anywhere.
+    pub wal: Option<WalConfig>,
+    /// How this replica misbehaves, if it does.
+    pub fault: Option<Fault>,
+    pub metrics: MetricsConfig,
+}
+
+/// The replicas of a cluster, and where clients can reach them.
+#[derive(Debug, Clone)]
+pub struct Cluster {
+    pub f: u32,
+    pub replicas: Vec<SocketAddr>,
+    pub clients: Vec<SocketAddr>,
+    pub listen: Option<SocketAddr>,
+    pub client_listen: Option<SocketAddr>,
+    pub auth: AuthConfig,
+    pub app: AppKind,
+}
+
+/// Contents of a cluster configuration file, e.g.
+///
+/// ```toml
+/// f = 1
+/// replicas = ["10.0.0.1:52000", "10.0.0.2:52000", "10.0.0.3:52000", "10.0.0.4:52000"]
+/// clients = ["10.0.0.1:53000", "10.0.0.2:53000", "10.0.0.3:53000", "10.0.0.4:53000"]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClusterFile {
+    n: Option<u32>,
+    f: Option<u32>,
+    #[serde(default)]
+    replicas: Vec<SocketAddr>,
+    #[serde(default)]
+    clients: Vec<SocketAddr>,
+    #[serde(default)]
+    localhost: bool,
+    base_port: Option<u16>,
+    listen: Option<SocketAddr>,
+    client_listen: Option<SocketAddr>,
+    auth: Option<AuthKind>,
+    key_dir: Option<PathBuf>,
+    app: Option<AppKind>,
+}
+
+/// Command line flags shared by replicas and clients. They take
+/// precedence over the values in the configuration file.
+#[derive(Debug, clap::Args)]
+pub struct ClusterArgs {
+    /// TOML file describing the cluster.
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Number of replicas; defaults to 3f+1 in localhost mode.
+    #[arg(long)]
+    pub n: Option<u32>,
+    /// Number of faulty replicas tolerated.
+    #[arg(long)]
+    pub f: Option<u32>,
+    /// Replica address, in id order. May be repeated.
+    #[arg(long = "replica")]
+    pub replicas: Vec<SocketAddr>,
+    /// Client endpoint of each replica, in id order. May be repeated.
+    /// Defaults to the replica addresses, with the port shifted by 1000.
+    #[arg(long = "client")]
+    pub clients: Vec<SocketAddr>,
+    /// Run every replica on 127.0.0.1, with consecutive ports.
+    #[arg(long)]
+    pub localhost: bool,
+    /// First port used in localhost mode.
+    #[arg(long)]
+    pub base_port: Option<u16>,
+    /// Address this replica accepts other replicas on.
+    #[arg(long)]
+    pub listen: Option<SocketAddr>,
+    /// Address this replica accepts clients on.
+    #[arg(long)]
+    pub client_listen: Option<SocketAddr>,
+    /// How replicas authenticate their messages.
+    #[arg(long, value_enum)]
+    pub auth: Option<AuthKind>,
+    /// Directory with the key files written by `keygen`.
+    #[arg(long)]
+    pub key_dir: Option<PathBuf>,
+    /// Application run by the replicas, and the
+    /// workload the clients generate for it.
+    #[arg(long, value_enum)]
+    pub app: Option<AppKind>,
+}
+
+impl ClusterArgs {
+    pub fn load(&self) -> io::Result<Cluster> {
+        let file = match self.config {
+            Some(ref path) => {
+                let contents = std::fs::read_to_string(path)?;
+                toml::from_str(&contents)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+            },
+            None => ClusterFile::default(),
+        };
+
+        let f = self.f.or(file.f).unwrap_or(1);
+        let n = self.n.or(file.n);
+        let (replicas, clients) = if self.localhost || file.localhost {
+            let n = n.unwrap_or(3*f + 1);
+            let base = self.base_port.or(file.base_port).unwrap_or(52000);
+            let addrs = |base| {
+                (0..n)
+                    .map(|i| Ok(SocketAddr::from((Ipv4Addr::LOCALHOST, shift(base, i)?))))
+                    .collect::<io::Result<Vec<_>>>()
+            };
+            let replicas = addrs(base)?;
+            let clients = addrs(shift(base, CLIENT_PORT_OFFSET.into())?)?;
+            (replicas, clients)
+        } else {
+            let replicas = if self.replicas.is_empty() { file.replicas } else { self.replicas.clone() };
+            let clients = if self.clients.is_empty() { file.clients } else { self.clients.clone() };
+            if replicas.is_empty() {
+                let e = io::Error::other("no replicas configured, use --config, --replica or --localhost");
+                return Err(e);
+            }
+            if n.is_some_and(|n| n as usize != replicas.len()) {
+                let e = io::Error::other("n doesn't match the no. of replica addresses");
+                return Err(e);
+            }
+            let clients = if clients.is_empty() {
+                replicas
+                    .iter()
+                    .map(|a| Ok(SocketAddr::new(a.ip(), shift(a.port(), CLIENT_PORT_OFFSET.into())?)))
+                    .collect::<io::Result<_>>()?
+            } else {
+                clients
+            };
+            (replicas, clients)
+        };
+        if clients.len() != replicas.len() {
+            let e = io::Error::other("expected one client endpoint per replica");
+            return Err(e);
+        }
+
+        Ok(Cluster {
+            f,
+            replicas,
+            clients,
+            listen: self.listen.or(file.listen),
+            client_listen: self.client_listen.or(file.client_listen),
+            auth: AuthConfig {
+                kind: self.auth.or(file.auth).unwrap_or_default(),
+                key_dir: self.key_dir.clone().or(file.key_dir).unwrap_or_else(|| "keys".into()),
+            },
+            app: self.app.or(file.app).unwrap_or_default(),
+        })
+    }
+}
+
+impl Cluster {
+    /// Where replica `id` accepts other replicas and clients. Unless
+    /// configured otherwise, it listens on every interface, on the
+    /// ports of its public addresses.
+    pub fn listen_addrs(&self, id: u32) -> Option<(SocketAddr, SocketAddr)> {
+        let any = |a: &SocketAddr| SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), a.port());
+        let replica = self.replicas.get(id as usize)?;
+        let client = self.clients.get(id as usize)?;
+        let listen = self.listen.unwrap_or_else(|| any(replica));
+        let client_listen = self.client_listen.unwrap_or_else(|| any(client));
+        Some((listen, client_listen))
+    }
+}
+
+// `port` moved up by `offset`, unless that is past the last port
+fn shift(port: u16, offset: u32) -> io::Result<u16> {
+    u16::try_from(offset)
+        .ok()
+        .and_then(|offset| port.checked_add(offset))
+        .ok_or_else(|| io::Error::other(format!("port {} + {} is out of range", port, offset)))
+}
@@ -0,0 +1,161 @@
+//! Deliberately faulty replicas, to check that the honest ones still
+//! agree with each other and make progress.
+//!
+//! Faults are injected in what a replica sends, so its own state stays
+//! that of a correct replica; the others only see it misbehave.
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::auth::Authenticator;
+use crate::log::batch_digest;
+use crate::message::{ConsensusMessage, ConsensusMessageKind, Signed, SystemMessage};
+use crate::view::Prepared;
+
+/// Delay of a slow replica, unless given otherwise.
+pub const SLOW_DELAY: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// Sends nothing, neither to the other replicas nor to clients.
+    Silent,
+    /// As the leader, proposes the batch without its last request
+    /// to the replicas with an odd id.
+    Equivocate,
+    /// Casts its prepares and commits on the instance after the
+    /// one they are for.
+    WrongSeq,
+    /// Holds everything it sends back for this long.
+    Slow(Duration),
+    /// In view changes, and in the new views it proposes as the
+    /// leader, claims a stable checkpoint far ahead and empty batches
+    /// prepared in the last view, backed by nothing but its own votes.
+    Lie,
+    /// Lies like `Lie`, but backs its claims with votes in the name
+    /// of every replica, which it can only authenticate as its own.
+    Forge,
+}
+
+/// The fault of one replica, given as `ID=KIND` on the command line.
+/// `KIND` is one of `silent`, `equivocate`, `wrong-seq`, `lie`, `forge` and `slow`,
+/// which may be followed by the delay in milliseconds, as in `slow:250`.
+#[derive(Debug, Copy, Clone)]
+pub struct FaultSpec {
+    pub id: u32,
+    pub fault: Fault,
+}
+
+impl Fault {
+    /// What the faulty replica sends to replica `to`, in a cluster of `n`,
+    /// in place of `m`; it can only authenticate what it forges with
+    /// its own `auth`.
+    pub fn tamper(&self, m: &SystemMessage, to: u32, n: u32, auth: &dyn Authenticator) -> Option<SystemMessage> {
+        let m = match m {
+            SystemMessage::Consensus(m) => m,
+            m => return if *self == Fault::Silent { None } else { Some(m.clone()) },
+        };
+        let tampered = match (self, &m.kind) {
+            (Fault::Silent, _) => return None,
+            (Fault::Equivocate, ConsensusMessageKind::PrePrepare(batch)) if to % 2 == 1 => {
+                let batch = batch[..batch.len().saturating_sub(1)].to_vec();
+                ConsensusMessage::new(m.from, m.seq, m.view, ConsensusMessageKind::PrePrepare(batch))
+            },
+            (Fault::WrongSeq, ConsensusMessageKind::Prepare(_) | ConsensusMessageKind::Commit(_)) => {
+                ConsensusMessage::new(m.from, m.seq + 1, m.view, m.kind.clone())
+            },
+            (Fault::Lie | Fault::Forge, ConsensusMessageKind::ViewChange(..)) => lie(m, &self.voters(m.from, n), auth),
+            (Fault::Lie | Fault::Forge, ConsensusMessageKind::NewView(proof)) => {
+                let voters = self.voters(m.from, n);
+                let proof = proof
+                    .iter()
+                    .map(|v| match v.message() {
+                        Some(v) if v.from == m.from => sign(lie(&v, &voters, auth), auth),
+                        _ => v.clone(),
+                    })
+                    .collect();
+                ConsensusMessage::new(m.from, m.seq, m.view, ConsensusMessageKind::NewView(proof))
+            },
+            _ => m.clone(),
+        };
+        Some(SystemMessage::Consensus(tampered))
+    }
+
+    // the replicas a liar backs its claims with votes from
+    fn voters(&self, liar: u32, n: u32) -> Vec<u32> {
+        match self {
+            Fault::Forge => (0..n).collect(),
+            _ => vec![liar; 3],
+        }
+    }
+
+    /// Whether the faulty replica sends anything to clients.
+    pub fn replies(&self) -> bool {
+        *self != Fault::Silent
+    }
+
+    /// How long the faulty replica holds each message back.
+    pub fn delay(&self) -> Option<Duration> {
+        match self {
+            Fault::Slow(delay) => Some(*delay),
+            _ => None,
+        }
+    }
+}
+
+// the `ViewChange` of a liar, in place of its own `m`, with
+// votes in the name of `voters`
+fn lie(m: &ConsensusMessage, voters: &[u32], auth: &dyn Authenticator) -> ConsensusMessage {
+    let prepared = match m.kind {
+        ConsensusMessageKind::ViewChange(_, ref prepared) => prepared,
+        _ => return m.clone(),
+    };
+    let ahead = m.seq + 1000;
+    let votes = |kind: ConsensusMessageKind, seq, view| -> Vec<Signed> {
+        voters
+            .iter()
+            .map(|&from| sign(ConsensusMessage::new(from, seq, view, kind.clone()), auth))
+            .collect()
+    };
+    let checkpoint = votes(ConsensusMessageKind::Checkpoint([0; 32]), ahead, 0);
+    let forge = |seq| {
+        let proof = votes(ConsensusMessageKind::Prepare(batch_digest(&[])), seq, m.view - 1);
+        Prepared { seq, view: m.view - 1, batch: Vec::new(), proof }
+    };
+    let first = prepared.first().map_or(m.seq, |p| p.seq);
+    let prepared = (first..m.seq + 4).map(forge).collect();
+    ConsensusMessage::new(m.from, ahead, m.view, ConsensusMessageKind::ViewChange(checkpoint, prepared))
+}
+
+// a vote as the liar would send it
+fn sign(m: ConsensusMessage, auth: &dyn Authenticator) -> Signed {
+    Signed::new(&SystemMessage::Consensus(m), auth)
+}
+
+impl FromStr for FaultSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (id, kind) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected ID=KIND, got {:?}", s))?;
+        let id = id
+            .parse()
+            .map_err(|_| format!("invalid replica id {:?}", id))?;
+        let fault = match kind.split_once(':') {
+            None if kind == "silent" => Fault::Silent,
+            None if kind == "equivocate" => Fault::Equivocate,
+            None if kind == "wrong-seq" => Fault::WrongSeq,
+            None if kind == "lie" => Fault::Lie,
+            None if kind == "forge" => Fault::Forge,
+            None if kind == "slow" => Fault::Slow(SLOW_DELAY),
+            Some(("slow", ms)) => {
+                let ms = ms
+                    .parse()
+                    .map_err(|_| format!("invalid delay {:?}", ms))?;
+                Fault::Slow(Duration::from_millis(ms))
+            },
+            _ => return Err(format!("unknown fault {:?}", kind)),
+        };
+        Ok(FaultSpec { id, fault })
+    }
+}
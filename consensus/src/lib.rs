@@ -0,0 +1,24 @@
+#[cfg(test)]
+mod tests;
+
+// XXX: for now focus on first come first serve,
+// then implement request queues, and work our
+// way up from there
+
+pub mod app;
+pub mod auth;
+pub mod cert;
+pub mod client;
+pub mod config;
+pub mod fault;
+pub mod log;
+pub mod message;
+pub mod metrics;
+pub mod node;
+pub mod peer;
+pub mod sim;
+pub mod system;
+pub mod transfer;
+pub mod util;
+pub mod view;
+pub mod wal;
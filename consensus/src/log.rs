@@ -0,0 +1,204 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use ring::digest;
+use serde::{Serialize, Deserialize};
+
+use crate::message::{ReplyMessage, RequestMessage, Signed};
+
+pub type Digest = [u8; 32];
+
+/// A value decided by the protocol.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Decision {
+    pub seq: i32,
+    pub digest: Digest,
+    pub batch: Vec<RequestMessage>,
+}
+
+/// The outcome of a checkpoint vote.
+#[derive(Debug, Copy, Clone)]
+pub enum Checkpoint {
+    /// Not enough matching votes yet.
+    Pending,
+    /// The checkpoint at `seq` became stable.
+    Stable(i32),
+    /// A quorum agrees on a digest we don't have for `seq`.
+    Diverged(i32),
+}
+
+/// The state of a replica after executing `seq`, taken when `seq`
+/// ends a checkpoint period.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub seq: i32,
+    /// Digest of the log up to `seq`, the one voted on in checkpoints.
+    pub log: Digest,
+    /// State of the application.
+    pub state: Vec<u8>,
+    /// The last reply to each client, ordered by client.
+    pub replies: Vec<ReplyMessage>,
+}
+
+impl Snapshot {
+    /// What replicas vote on in checkpoints, covering both
+    /// the decided values and the state they led to.
+    pub fn digest(&self) -> Digest {
+        digest(&bincode::serialize(self).unwrap())
+    }
+}
+
+/// Log of decided values, truncated at stable checkpoints.
+#[derive(Debug)]
+pub struct Log {
+    period: i32,
+    // first seq not covered by the last stable checkpoint
+    low: i32,
+    // digest of the state after executing everything in the log
+    last: Digest,
+    decisions: VecDeque<Decision>,
+    // our own digest of each checkpoint we haven't seen become stable
+    own: BTreeMap<i32, Digest>,
+    // the frame of each vote, by digest and then voter
+    votes: BTreeMap<i32, HashMap<Digest, BTreeMap<u32, Signed>>>,
+    // the last checkpoint we saw a quorum vote for, and the votes
+    proven: Option<(i32, Digest, Vec<Signed>)>,
+}
+
+impl Log {
+    pub fn new(period: i32) -> Self {
+        Log {
+            period,
+            low: 0,
+            last: [0; 32],
+            decisions: VecDeque::new(),
+            own: BTreeMap::new(),
+            votes: BTreeMap::new(),
+            proven: None,
+        }
+    }
+
+    /// Sequence numbers below this one are covered by a stable checkpoint.
+    pub fn low_watermark(&self) -> i32 {
+        self.low
+    }
+
+    /// Messages at or above this sequence number are not accepted,
+    /// which bounds the amount of state kept per replica.
+    pub fn high_watermark(&self) -> i32 {
+        self.low + 2*self.period
+    }
+
+    /// Decisions since the last stable checkpoint, in order.
+    pub fn decisions(&self) -> impl Iterator<Item = &Decision> {
+        self.decisions.iter()
+    }
+
+    /// Append a newly executed value. If `seq` ends a checkpoint period,
+    /// returns the digest of the log up to it, for its `Snapshot`.
+    pub fn decide(&mut self, decision: Decision) -> Option<Digest> {
+        let mut ctx = digest::Context::new(&digest::SHA256);
+        ctx.update(&self.last);
+        ctx.update(&decision.seq.to_be_bytes());
+        ctx.update(&decision.digest);
+        self.last.copy_from_slice(ctx.finish().as_ref());
+        let seq = decision.seq;
+        self.decisions.push_back(decision);
+
+        if (seq + 1) % self.period == 0 {
+            Some(self.last)
+        } else {
+            None
+        }
+    }
+
+    /// Our digest of the checkpoint at `seq`, the one
+    /// the other replicas' votes are compared with.
+    pub fn checkpointed(&mut self, seq: i32, digest: Digest) {
+        self.own.insert(seq, digest);
+    }
+
+    /// Resume from a checkpoint at `seq`, whose digest is `last`.
+    pub fn restore(&mut self, seq: i32, last: Digest) {
+        self.last = last;
+        self.decisions.clear();
+        self.truncate(seq);
+    }
+
+    /// Account for the checkpoint digest `from` computed at `seq`,
+    /// which arrived in `frame`.
+    pub fn checkpoint(&mut self, seq: i32, from: u32, digest: Digest, quorum: u32, frame: Signed) -> Checkpoint {
+        if seq < self.low || seq >= self.high_watermark() {
+            return Checkpoint::Pending;
+        }
+        let voters = self.votes
+            .entry(seq)
+            .or_default()
+            .entry(digest)
+            .or_default();
+        voters.entry(from).or_insert(frame);
+        if (voters.len() as u32) < quorum {
+            return Checkpoint::Pending;
+        }
+        let voters = voters.values().cloned().collect();
+        let checkpoint = self.settle(seq, digest);
+        if let Checkpoint::Stable(seq) = checkpoint {
+            self.proven = Some((seq, digest, voters));
+        }
+        checkpoint
+    }
+
+    /// The last checkpoint that became stable through the votes of a
+    /// quorum, rather than a state transfer, along with their frames.
+    pub fn proven(&self) -> Option<&(i32, Digest, Vec<Signed>)> {
+        self.proven.as_ref()
+    }
+
+    /// Account for a checkpoint at `seq` that enough replicas vouched
+    /// for in a state transfer, which we executed past on our own.
+    pub fn vouched(&mut self, seq: i32, digest: Digest) -> Checkpoint {
+        if seq < self.low {
+            return Checkpoint::Pending;
+        }
+        self.settle(seq, digest)
+    }
+
+    // compare our own digest with the stable one
+    fn settle(&mut self, seq: i32, digest: Digest) -> Checkpoint {
+        match self.own.get(&seq) {
+            // we haven't got there yet
+            None => Checkpoint::Pending,
+            Some(own) if *own != digest => Checkpoint::Diverged(seq),
+            Some(_) => {
+                self.truncate(seq);
+                Checkpoint::Stable(seq)
+            },
+        }
+    }
+
+    fn truncate(&mut self, seq: i32) {
+        self.low = seq + 1;
+        while let Some(d) = self.decisions.front() {
+            if d.seq > seq {
+                break;
+            }
+            self.decisions.pop_front();
+        }
+        self.own = self.own.split_off(&self.low);
+        self.votes = self.votes.split_off(&self.low);
+    }
+}
+
+pub fn digest(buf: &[u8]) -> Digest {
+    let mut d = [0; 32];
+    d.copy_from_slice(digest::digest(&digest::SHA256, buf).as_ref());
+    d
+}
+
+pub fn batch_digest(batch: &[RequestMessage]) -> Digest {
+    digest(&bincode::serialize(batch).unwrap())
+}
+
+/// Hex encoding of the first bytes of a digest, for logging.
+pub fn short(d: &Digest) -> String {
+    d[..4].iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,143 @@
+use tokio::net::tcp::OwnedWriteHalf;
+use serde::{Serialize, Deserialize};
+
+use crate::auth::Authenticator;
+use crate::log::{Decision, Digest, Snapshot};
+use crate::view::Prepared;
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    DisconnectedTx(u32),
+    DisconnectedRx(u32),
+    DisconnectedClient(u32),
+    // a frame from this replica failed authentication
+    Unauthenticated(u32),
+}
+
+#[derive(Debug)]
+pub enum Message {
+    System(SystemMessage),
+    ConnectedClient(u32, OwnedWriteHalf),
+    Timeout(u64),
+    BatchTimeout,
+    Error(ErrorKind),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SystemMessage {
+    Request(RequestMessage),
+    Consensus(ConsensusMessage),
+}
+
+/// An operation submitted by a client. Each client numbers its
+/// requests sequentially, starting from zero.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestMessage {
+    pub client: u32,
+    pub id: u32,
+    // encoded by the application's own format
+    pub op: Vec<u8>,
+}
+
+/// Sent by every replica to the client, after executing its request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplyMessage {
+    pub client: u32,
+    pub id: u32,
+    // the instance the request was decided on
+    pub seq: i32,
+    // what the application returned
+    pub result: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsensusMessage {
+    pub seq: i32,
+    pub view: u32,
+    pub from: u32,
+    pub kind: ConsensusMessageKind,
+    /// The frame the message arrived in, kept so that it
+    /// can be relayed in the proofs of view changes.
+    #[serde(skip)]
+    pub frame: Option<Signed>,
+}
+
+/// A consensus message as its sender authenticated it: the serialized
+/// `SystemMessage`, and the tag of its frame. Proofs are made of these,
+/// so that each replica checks who said what for itself, rather than
+/// taking the word of the replica relaying them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Signed {
+    pub payload: Vec<u8>,
+    pub tag: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ConsensusMessageKind {
+    PrePrepare(Vec<RequestMessage>),
+    Prepare(Digest),
+    Commit(Digest),
+    // `seq` is the first instance the sender hasn't executed,
+    // and `view` is the view it wants to move to; it carries the
+    // `Checkpoint` messages that made its last checkpoint stable,
+    // and what it prepared after that checkpoint
+    ViewChange(Vec<Signed>, Vec<Prepared>),
+    // `seq` is the first instance of the new view; it carries
+    // the `ViewChange` messages of a quorum
+    NewView(Vec<Signed>),
+    // digest of the snapshot taken after executing `seq`
+    Checkpoint(Digest),
+    // `seq` is the first instance the sender hasn't executed
+    FetchState,
+    // the sender's last stable checkpoint, and what it decided
+    // after it; `view` is its view, and `seq` the first instance
+    // of that view
+    State(Box<Snapshot>, Vec<Decision>),
+}
+
+impl ConsensusMessage {
+    pub fn new(from: u32, seq: i32, view: u32, kind: ConsensusMessageKind) -> Self {
+        Self { seq, view, from, kind, frame: None }
+    }
+
+    /// The frame the message arrived in, or an empty one
+    /// that won't pass for anyone's, if it was never sent.
+    pub fn take_frame(&mut self) -> Signed {
+        self.frame.take().unwrap_or_default()
+    }
+}
+
+impl ConsensusMessageKind {
+    /// Whether messages of this kind are relayed in proofs,
+    /// and so keep the frame they arrived in.
+    pub fn relayed(&self) -> bool {
+        matches!(
+            self,
+            ConsensusMessageKind::Prepare(_) | ConsensusMessageKind::Checkpoint(_) | ConsensusMessageKind::ViewChange(..)
+        )
+    }
+}
+
+impl Signed {
+    /// Authenticate `m` as we would to send it.
+    pub fn new(m: &SystemMessage, auth: &dyn Authenticator) -> Self {
+        let payload = bincode::serialize(m).unwrap();
+        let tag = auth.tag(&payload);
+        Signed { payload, tag }
+    }
+
+    /// The message, if the tag is that of its sender.
+    pub fn open(&self, auth: &dyn Authenticator) -> Option<ConsensusMessage> {
+        let m = self.message()?;
+        auth.verify(m.from, &self.payload, &self.tag).then_some(m)
+    }
+
+    /// The message, whoever authenticated it; only for
+    /// proofs that were opened before.
+    pub fn message(&self) -> Option<ConsensusMessage> {
+        match bincode::deserialize(&self.payload) {
+            Ok(SystemMessage::Consensus(m)) => Some(m),
+            _ => None,
+        }
+    }
+}
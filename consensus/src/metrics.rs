@@ -0,0 +1,249 @@
+//! Timestamps of each consensus instance, as seen by one replica.
+//!
+//! The phases are named like the `Measurements` of `atlas_metrics`,
+//! so the results compare with the febft microbenchmarks:
+//!
+//! - pre-prepare: from receiving the oldest request of the batch
+//!   until accepting the leader's proposal;
+//! - prepare: from accepting the proposal until a prepare quorum;
+//! - commit: from a prepare quorum until a commit quorum;
+//! - execution: from a commit quorum until executing the batch,
+//!   which waits for the instances before it.
+//!
+//! Consensus is the sum of prepare and commit, as in `atlas_metrics`.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use tokio::io;
+
+use crate::message::RequestMessage;
+
+#[derive(Debug, Clone, Default)]
+pub struct MetricsConfig {
+    /// How often to print a summary, if at all.
+    pub interval: Option<Duration>,
+    /// Where to write every instance's timestamps, at shutdown.
+    pub csv: Option<PathBuf>,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Timestamps {
+    received: Option<Instant>,
+    proposed: Instant,
+    prepared: Option<Instant>,
+    committed: Option<Instant>,
+}
+
+/// An executed instance.
+#[derive(Debug, Copy, Clone)]
+pub struct Sample {
+    pub seq: i32,
+    pub batch: usize,
+    pub received: Option<Instant>,
+    pub proposed: Instant,
+    pub prepared: Instant,
+    pub committed: Instant,
+    pub executed: Instant,
+}
+
+#[derive(Debug)]
+pub struct Metrics {
+    id: u32,
+    cfg: MetricsConfig,
+    start: Instant,
+    // when each request waiting to be decided arrived
+    received: HashMap<(u32, u32), Instant>,
+    // the instances we accepted a proposal for
+    running: HashMap<i32, Timestamps>,
+    samples: Vec<Sample>,
+    // where the current reporting interval starts
+    last: Instant,
+    reported: usize,
+    ops: usize,
+    max_throughput: f64,
+}
+
+impl Metrics {
+    pub fn new(id: u32, cfg: MetricsConfig, now: Instant) -> Self {
+        Metrics {
+            id,
+            cfg,
+            start: now,
+            received: HashMap::new(),
+            running: HashMap::new(),
+            samples: Vec::new(),
+            last: now,
+            reported: 0,
+            ops: 0,
+            max_throughput: 0.0,
+        }
+    }
+
+    pub fn received(&mut self, request: &RequestMessage, now: Instant) {
+        self.received.entry((request.client, request.id)).or_insert(now);
+    }
+
+    pub fn proposed(&mut self, seq: i32, batch: &[RequestMessage], now: Instant) {
+        let received = batch
+            .iter()
+            .filter_map(|r| self.received.get(&(r.client, r.id)))
+            .min()
+            .copied();
+        let timestamps = Timestamps { received, proposed: now, prepared: None, committed: None };
+        self.running.insert(seq, timestamps);
+    }
+
+    pub fn prepared(&mut self, seq: i32, now: Instant) {
+        if let Some(t) = self.running.get_mut(&seq) {
+            t.prepared = Some(now);
+        }
+    }
+
+    pub fn committed(&mut self, seq: i32, now: Instant) {
+        if let Some(t) = self.running.get_mut(&seq) {
+            // a commit quorum may also end the prepare phase
+            t.prepared.get_or_insert(now);
+            t.committed = Some(now);
+        }
+    }
+
+    /// Note that `batch` was executed on `seq`. Instances decided
+    /// without us taking part, e.g. fetched from the others, or
+    /// recovered from the disk, are not sampled.
+    pub fn executed(&mut self, seq: i32, batch: &[RequestMessage], now: Instant) {
+        for r in batch {
+            self.received.remove(&(r.client, r.id));
+        }
+        self.ops += batch.len();
+        let sample = self.running
+            .remove(&seq)
+            .and_then(|t| {
+                Some(Sample {
+                    seq,
+                    batch: batch.len(),
+                    received: t.received,
+                    proposed: t.proposed,
+                    prepared: t.prepared?,
+                    committed: t.committed?,
+                    executed: now,
+                })
+            });
+        self.samples.extend(sample);
+        if self.cfg.interval.is_some_and(|interval| now >= self.last + interval) {
+            self.report(now);
+        }
+    }
+
+    /// Forget the instances from `seq` onwards, which are
+    /// going to be proposed again in a new view.
+    pub fn discard_from(&mut self, seq: i32) {
+        self.running.retain(|&s, _| s < seq);
+    }
+
+    /// Forget the requests executed without us, keeping those
+    /// for which `waiting(client, id)` holds.
+    pub fn retain_requests(&mut self, waiting: impl Fn(u32, u32) -> bool) {
+        self.received.retain(|&(client, id), _| waiting(client, id));
+    }
+
+    /// Print the last summary, and write the CSV.
+    pub fn finish(&mut self, now: Instant) -> io::Result<()> {
+        if self.cfg.interval.is_some() && self.reported < self.samples.len() {
+            self.report(now);
+        }
+        match self.cfg.csv {
+            Some(ref path) => self.write_csv(File::create(path)?),
+            None => Ok(()),
+        }
+    }
+
+    // summary of the instances executed since the last one
+    fn report(&mut self, now: Instant) {
+        let samples = &self.samples[self.reported..];
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        let throughput = self.ops as f64 / elapsed;
+        self.max_throughput = self.max_throughput.max(throughput);
+        println!("r{} // --- Measurements after {} instances ({} samples) ---", self.id, self.samples.len(), samples.len());
+        println!(
+            "r{} // Throughput = {:.2} operations/sec (Maximum observed: {:.2} ops/sec)",
+            self.id,
+            throughput,
+            self.max_throughput,
+        );
+        println!("r{} // Batch throughput = {:.2} batches/sec", self.id, samples.len() as f64 / elapsed);
+        if !samples.is_empty() {
+            let batch = samples.iter().map(|s| s.batch).sum::<usize>() as f64 / samples.len() as f64;
+            println!("r{} // Batch size = {:.2} requests", self.id, batch);
+        }
+        for (phase, latency) in PHASES {
+            let mut latencies: Vec<_> = samples.iter().filter_map(latency).collect();
+            latencies.sort_unstable();
+            println!(
+                "r{} // {} latency: p50 {:?} p90 {:?} p99 {:?} max {:?}",
+                self.id,
+                phase,
+                percentile(&latencies, 0.50),
+                percentile(&latencies, 0.90),
+                percentile(&latencies, 0.99),
+                latencies.last().copied().unwrap_or_default(),
+            );
+        }
+        self.reported = self.samples.len();
+        self.last = now;
+        self.ops = 0;
+    }
+
+    /// One line per executed instance, with the time of each event
+    /// in microseconds since the replica started, followed by the
+    /// latency of each phase, in microseconds.
+    pub fn write_csv(&self, w: impl Write) -> io::Result<()> {
+        let mut w = BufWriter::new(w);
+        write!(w, "seq,batch,received,proposed,prepared,committed,executed")?;
+        for (phase, _) in PHASES {
+            write!(w, ",{}", phase)?;
+        }
+        writeln!(w)?;
+        let micros = |t: Instant| t.duration_since(self.start).as_micros();
+        for s in self.samples.iter() {
+            write!(w, "{},{},", s.seq, s.batch)?;
+            if let Some(received) = s.received {
+                write!(w, "{}", micros(received))?;
+            }
+            let events = [s.proposed, s.prepared, s.committed, s.executed];
+            for t in events {
+                write!(w, ",{}", micros(t))?;
+            }
+            for (_, latency) in PHASES {
+                write!(w, ",")?;
+                if let Some(latency) = latency(s) {
+                    write!(w, "{}", latency.as_micros())?;
+                }
+            }
+            writeln!(w)?;
+        }
+        w.flush()
+    }
+}
+
+type Phase = (&'static str, fn(&Sample) -> Option<Duration>);
+
+const PHASES: [Phase; 5] = [
+    ("pre-prepare", |s| Some(s.proposed.saturating_duration_since(s.received?))),
+    ("prepare", |s| Some(s.prepared - s.proposed)),
+    ("commit", |s| Some(s.committed - s.prepared)),
+    ("consensus", |s| Some(s.committed - s.proposed)),
+    ("execution", |s| Some(s.executed - s.committed)),
+];
+
+/// The `p`-th percentile of `sorted`, which is in ascending order.
+pub fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::default();
+    }
+    let i = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted[i]
+}
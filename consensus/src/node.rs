@@ -0,0 +1,467 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::io;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::sync::mpsc;
+use tokio::sync::Mutex;
+use smallvec::{smallvec, SmallVec};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::auth::Authenticator;
+use crate::fault::Fault;
+use crate::log::Decision;
+use crate::peer::{Frame, Peer};
+use crate::message::{
+    ConsensusMessage,
+    ErrorKind,
+    Message,
+    ReplyMessage,
+    RequestMessage,
+    Signed,
+    SystemMessage,
+};
+
+/// Largest frame we read from a connection, including its tag;
+/// enough for a snapshot of the key-value store in state transfers.
+const MAX_FRAME: usize = 64 * 1024 * 1024;
+
+/// What a replica needs from the outside world. Implemented by
+/// `Node` over TCP, and by the simulator in memory.
+pub trait Network {
+    fn id(&self) -> u32;
+
+    fn broadcast(&self, m: SystemMessage, targets: impl Iterator<Item = u32>);
+
+    /// Send the result of a request back to its client.
+    fn reply(&self, m: ReplyMessage);
+
+    /// Deliver `Message::Timeout(timer)` to ourselves, `after` from now.
+    fn timeout(&self, after: Duration, timer: u64);
+
+    /// Deliver `Message::BatchTimeout` to ourselves, `after` from now.
+    fn wake_up(&self, after: Duration);
+
+    fn now(&self) -> Instant;
+
+    /// Checks the frames relayed in proofs, as it does
+    /// those of the messages it receives.
+    fn auth(&self) -> &dyn Authenticator;
+
+    /// Called on every value we execute, in order; the simulator
+    /// checks them against those of the other replicas.
+    fn decided(&self, _decision: &Decision) {}
+
+    /// Called when we skip to the checkpoint at `seq`, instead
+    /// of executing the values before it.
+    fn restored(&self, _seq: i32) {}
+}
+
+#[derive(Debug)]
+pub struct Node {
+    pub id: u32,
+    others_tx: HashMap<u32, Peer>,
+    auth: Arc<dyn Authenticator>,
+    clients: HashMap<u32, Arc<Mutex<OwnedWriteHalf>>>,
+    // ids of the clients with a connection, shared with
+    // the tasks accepting new ones
+    connected: Arc<std::sync::Mutex<HashSet<u32>>>,
+    // messages we sent to ourselves, in their frame
+    loopback: std::sync::Mutex<VecDeque<SystemMessage>>,
+    fault: Option<Fault>,
+    my_tx: MessageChannelTx,
+    my_rx: MessageChannelRx,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct MessageChannelTx {
+    other: mpsc::Sender<Message>,
+    requests: mpsc::Sender<RequestMessage>,
+    consensus: mpsc::Sender<ConsensusMessage>,
+}
+
+#[derive(Debug)]
+pub(crate) struct MessageChannelRx {
+    other: mpsc::Receiver<Message>,
+    requests: mpsc::Receiver<RequestMessage>,
+    consensus: mpsc::Receiver<ConsensusMessage>,
+}
+
+impl Node {
+    /// Connect to every other replica, and start accepting
+    /// client connections on `client_listen`.
+    pub async fn bootstrap(
+        id: u32,
+        addrs: Vec<SocketAddr>,
+        listen: SocketAddr,
+        client_listen: SocketAddr,
+        auth: Arc<dyn Authenticator>,
+        fault: Option<Fault>,
+    ) -> io::Result<Self> {
+        let n = addrs.len() as u32;
+
+        let listener = TcpListener::bind(listen).await?;
+        let client_listener = TcpListener::bind(client_listen).await?;
+
+        let (tx, rx) = new_message_channel(128);
+
+        // rx side (accept conns from replica)
+        let tx_clone = tx.clone();
+        let auth_clone = Arc::clone(&auth);
+        tokio::spawn(async move {
+            let tx = tx_clone;
+            loop {
+                if let Ok((conn, _)) = listener.accept().await {
+                    let tx = tx.clone();
+                    let auth = Arc::clone(&auth_clone);
+                    tokio::spawn(accept_replica(conn, tx, auth));
+                }
+            }
+        });
+
+        // tx side (connect to replica); peers that are down don't
+        // hold us back, they are reached once they come up
+        let others_tx = (0..n)
+            .filter(|&x| x != id)
+            .map(|other_id| {
+                let peer = Peer::connect(id, other_id, addrs[other_id as usize], tx.clone());
+                (other_id, peer)
+            })
+            .collect();
+
+        // clients may connect at any time
+        let tx_clone = tx.clone();
+        let connected = Arc::new(std::sync::Mutex::new(HashSet::new()));
+        let connected_clone = Arc::clone(&connected);
+        tokio::spawn(async move {
+            let (tx, connected) = (tx_clone, connected_clone);
+            loop {
+                if let Ok((conn, _)) = client_listener.accept().await {
+                    let tx = tx.clone();
+                    tokio::spawn(accept_client(conn, tx, Arc::clone(&connected)));
+                }
+            }
+        });
+
+        Ok(Node {
+            id,
+            others_tx,
+            auth,
+            clients: HashMap::new(),
+            connected,
+            loopback: std::sync::Mutex::new(VecDeque::new()),
+            fault,
+            my_tx: tx,
+            my_rx: rx,
+        })
+    }
+
+    // a length prefixed frame, followed by its authentication tag
+    fn frame(&self, m: &SystemMessage) -> Frame {
+        let mut buf = vec![0; 4];
+        bincode::serialize_into(&mut buf, m).unwrap();
+        let tag = self.auth.tag(&buf[4..]);
+        buf.extend_from_slice(&tag);
+        let len = (buf.len() as u32 - 4).to_be_bytes();
+        buf[..4].copy_from_slice(&len);
+        buf.into()
+    }
+
+    // our own `m`, as if it went through the network in `frame`
+    fn framed(&self, mut m: ConsensusMessage, frame: &[u8]) -> ConsensusMessage {
+        let (payload, tag) = frame[4..].split_at(frame.len() - 4 - self.auth.tag_len());
+        m.frame = Some(Signed { payload: payload.to_vec(), tag: tag.to_vec() });
+        m
+    }
+
+    pub fn client_connected(&mut self, client: u32, conn: OwnedWriteHalf) {
+        self.clients.insert(client, Arc::new(Mutex::new(conn)));
+    }
+
+    /// Forget a client, whose id may now be taken by a new connection.
+    pub fn client_disconnected(&mut self, client: u32) {
+        self.clients.remove(&client);
+        self.connected.lock().unwrap().remove(&client);
+    }
+
+    pub async fn receive(&mut self) -> io::Result<Message> {
+        if let Some(m) = self.loopback.get_mut().unwrap().pop_front() {
+            return Ok(Message::System(m));
+        }
+        self.my_rx.recv().await
+            .ok_or_else(|| io::Error::other("receive failed"))
+    }
+}
+
+impl Network for Node {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn broadcast(&self, m: SystemMessage, targets: impl Iterator<Item = u32>) {
+        // serialized and authenticated once for all the replicas
+        let mut frame = None;
+        let n = self.others_tx.len() as u32 + 1;
+        let mut to_self = false;
+        for id in targets {
+            if id == self.id {
+                to_self = true;
+                continue;
+            }
+            let frame = match self.fault {
+                None => Arc::clone(frame.get_or_insert_with(|| self.frame(&m))),
+                Some(fault) => match fault.tamper(&m, id, n, &*self.auth) {
+                    Some(m) => self.frame(&m),
+                    None => continue,
+                },
+            };
+            // buffered if the peer is down
+            let peer = &self.others_tx[&id];
+            match self.fault.and_then(|fault| fault.delay()) {
+                Some(delay) => {
+                    let peer = peer.clone();
+                    tokio::spawn(async move {
+                        tokio::time::sleep(delay).await;
+                        peer.send(frame);
+                    });
+                },
+                None => peer.send(frame),
+            }
+        }
+        if to_self {
+            // our own votes are relayed in proofs too
+            let m = match m {
+                SystemMessage::Consensus(c) if c.kind.relayed() => {
+                    let frame = frame.unwrap_or_else(|| self.frame(&SystemMessage::Consensus(c.clone())));
+                    SystemMessage::Consensus(self.framed(c, &frame))
+                },
+                m => m,
+            };
+            self.loopback.lock().unwrap().push_back(m);
+        }
+    }
+
+    // the client may have disconnected
+    fn reply(&self, m: ReplyMessage) {
+        let conn = match self.clients.get(&m.client) {
+            Some(conn) => Arc::clone(conn),
+            None => return,
+        };
+        if self.fault.is_some_and(|fault| !fault.replies()) {
+            return;
+        }
+        let delay = self.fault.and_then(|fault| fault.delay());
+        tokio::spawn(async move {
+            if let Some(delay) = delay {
+                tokio::time::sleep(delay).await;
+            }
+            let mut conn = conn.lock().await;
+            write_frame(&mut *conn, &m).await.unwrap_or(());
+        });
+    }
+
+    fn timeout(&self, after: Duration, timer: u64) {
+        let tx = self.my_tx.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(after).await;
+            tx.send(Message::Timeout(timer)).await.unwrap_or(());
+        });
+    }
+
+    fn wake_up(&self, after: Duration) {
+        let tx = self.my_tx.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(after).await;
+            tx.send(Message::BatchTimeout).await.unwrap_or(());
+        });
+    }
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn auth(&self) -> &dyn Authenticator {
+        &*self.auth
+    }
+}
+
+// a replica identifies itself, and then only sends its own
+// consensus messages, each followed by an authentication tag
+async fn accept_replica(conn: TcpStream, tx: MessageChannelTx, auth: Arc<dyn Authenticator>) {
+    // frames come in coalesced, read them a buffer at a time
+    let mut conn = BufReader::new(conn);
+    let id = match conn.read_u32().await {
+        Ok(id) => id,
+        Err(_) => return,
+    };
+    loop {
+        let buf = match read_raw_frame(&mut conn).await {
+            Ok(buf) => buf,
+            Err(_) => {
+                let m = Message::Error(ErrorKind::DisconnectedRx(id));
+                tx.send(m).await.unwrap_or(());
+                return;
+            },
+        };
+        let split = match buf.len().checked_sub(auth.tag_len()) {
+            Some(split) => split,
+            None => continue,
+        };
+        let (payload, tag) = buf.split_at(split);
+        if !auth.verify(id, payload, tag) {
+            let m = Message::Error(ErrorKind::Unauthenticated(id));
+            tx.send(m).await.unwrap_or(());
+            continue;
+        }
+        match bincode::deserialize(payload) {
+            Ok(SystemMessage::Consensus(mut m)) if m.from == id => {
+                if m.kind.relayed() {
+                    m.frame = Some(Signed { payload: payload.to_vec(), tag: tag.to_vec() });
+                }
+                tx.send(Message::System(SystemMessage::Consensus(m))).await.unwrap_or(());
+            },
+            // garbage, or speaking for another replica
+            _ => continue,
+        }
+    }
+}
+
+// a client identifies itself, and then only sends requests; the ids
+// in `connected` are taken, until the replica forgets their client
+async fn accept_client(conn: TcpStream, tx: MessageChannelTx, connected: Arc<std::sync::Mutex<HashSet<u32>>>) {
+    // replies are small, don't hold them back
+    conn.set_nodelay(true).unwrap_or(());
+    let (mut rx_half, tx_half) = conn.into_split();
+    let client = match rx_half.read_u32().await {
+        Ok(client) => client,
+        Err(_) => return,
+    };
+    if !connected.lock().unwrap().insert(client) {
+        // it would take the replies of the connected one
+        return;
+    }
+    tx.send(Message::ConnectedClient(client, tx_half)).await.unwrap_or(());
+    loop {
+        let m: RequestMessage = match read_frame(&mut rx_half).await {
+            Ok(m) => m,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => continue,
+            Err(_) => {
+                let m = Message::Error(ErrorKind::DisconnectedClient(client));
+                tx.send(m).await.unwrap_or(());
+                return;
+            },
+        };
+        // don't let clients speak for each other
+        if m.client != client {
+            continue;
+        }
+        tx.send(Message::System(SystemMessage::Request(m))).await.unwrap_or(());
+    }
+}
+
+/// Write `m` prefixed by its length, as a big endian `u32`.
+pub async fn write_frame<W, T>(w: &mut W, m: &T) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    let mut buf: SmallVec<[_; 8192]> = smallvec![0; 4];
+    bincode::serialize_into(&mut buf, m).unwrap();
+    let len = (buf.len() as u32 - 4).to_be_bytes();
+    buf[..4].copy_from_slice(&len);
+    w.write_all(&buf).await
+}
+
+/// Read a frame written by `write_frame`. Frames that can't be
+/// decoded yield an `InvalidData` error, and the stream may still
+/// be used afterwards.
+pub async fn read_frame<R, T>(r: &mut R) -> io::Result<T>
+where
+    R: AsyncRead + Unpin,
+    T: DeserializeOwned,
+{
+    let buf = read_raw_frame(r).await?;
+    bincode::deserialize(&buf)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub(crate) async fn read_raw_frame<R>(r: &mut R) -> io::Result<SmallVec<[u8; 8192]>>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut size = [0; 4];
+    r.read_exact(&mut size[..]).await?;
+    let size = u32::from_be_bytes(size) as usize;
+    // the frame isn't authenticated yet, so its
+    // length mustn't make us allocate much
+    if size > MAX_FRAME {
+        let e = format!("frame of {} bytes exceeds the limit of {}", size, MAX_FRAME);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+    }
+    let mut buf = smallvec![0; size];
+    r.read_exact(&mut buf[..]).await?;
+    Ok(buf)
+}
+
+pub(crate) fn new_message_channel(bound: usize) -> (MessageChannelTx, MessageChannelRx) {
+    let (c_tx, c_rx) = mpsc::channel(bound);
+    let (r_tx, r_rx) = mpsc::channel(bound);
+    let (o_tx, o_rx) = mpsc::channel(bound);
+    let tx = MessageChannelTx {
+        consensus: c_tx,
+        requests: r_tx,
+        other: o_tx,
+    };
+    let rx = MessageChannelRx {
+        consensus: c_rx,
+        requests: r_rx,
+        other: o_rx,
+    };
+    (tx, rx)
+}
+
+impl MessageChannelTx {
+    pub(crate) async fn send(&self, message: Message) -> Result<(), Message> {
+        match message {
+            Message::System(message) => {
+                match message {
+                    SystemMessage::Request(message) => {
+                        self.requests
+                            .send(message)
+                            .await
+                            .map_err(|e| Message::System(SystemMessage::Request(e.0)))
+                    },
+                    SystemMessage::Consensus(message) => {
+                        self.consensus
+                            .send(message)
+                            .await
+                            .map_err(|e| Message::System(SystemMessage::Consensus(e.0)))
+                    },
+                }
+            },
+            _ => {
+                self.other
+                    .send(message)
+                    .await
+                    .map_err(|e| e.0)
+            },
+        }
+    }
+}
+
+impl MessageChannelRx {
+    pub(crate) async fn recv(&mut self) -> Option<Message> {
+        let message = tokio::select! {
+            c = self.consensus.recv() => Message::System(SystemMessage::Consensus(c?)),
+            r = self.requests.recv() => Message::System(SystemMessage::Request(r?)),
+            o = self.other.recv() => o?,
+        };
+        Some(message)
+    }
+}
+
@@ -0,0 +1,173 @@
+use std::collections::VecDeque;
+use std::io::IoSlice;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::message::{ErrorKind, Message};
+use crate::node::MessageChannelTx;
+
+/// Frames queued for the writer task of a peer. It is drained even
+/// while the peer is slow or down, so it only fills up when the
+/// task doesn't get to run; new frames are then dropped, as if the
+/// peer had lost them.
+const QUEUE_SIZE: usize = 1024;
+
+/// Frames kept for a peer while it is unreachable. The oldest
+/// ones are dropped first.
+const MAX_PENDING: usize = 4096;
+
+/// Most frames handed to the kernel in a single write.
+const MAX_IOVECS: usize = 64;
+
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// A peer that doesn't drain its socket for this long is
+/// considered down.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A frame, shared by every peer it is broadcast to.
+pub type Frame = Arc<[u8]>;
+
+/// Outgoing connection to another replica, owned by a writer task.
+/// It is established in the background, and again whenever a write
+/// fails, retrying with an exponential backoff. Frames sent in the
+/// meantime are buffered, and flushed once the connection is back.
+#[derive(Debug, Clone)]
+pub struct Peer {
+    tx: mpsc::Sender<Frame>,
+}
+
+// the frames not written yet, the first of which
+// may have been partially written already
+#[derive(Debug, Default)]
+struct Pending {
+    frames: VecDeque<Frame>,
+    written: usize,
+}
+
+impl Peer {
+    /// Start connecting to replica `id` at `addr`, identifying
+    /// ourselves as replica `me`. Lost connections are reported
+    /// through `tx`.
+    pub(crate) fn connect(me: u32, id: u32, addr: SocketAddr, tx: MessageChannelTx) -> Self {
+        let (frames_tx, frames_rx) = mpsc::channel(QUEUE_SIZE);
+        tokio::spawn(writer(me, id, addr, frames_rx, tx));
+        Peer { tx: frames_tx }
+    }
+
+    /// Queue a frame for the peer, without waiting for it to be written.
+    pub fn send(&self, frame: Frame) {
+        // the writer fell too far behind; the protocol
+        // copes with lost messages
+        self.tx.try_send(frame).unwrap_or(());
+    }
+}
+
+async fn writer(me: u32, id: u32, addr: SocketAddr, mut rx: mpsc::Receiver<Frame>, tx: MessageChannelTx) {
+    let mut pending = Pending::default();
+    loop {
+        // keep taking frames while the peer is down
+        let connecting = establish(me, addr);
+        tokio::pin!(connecting);
+        let mut conn = loop {
+            tokio::select! {
+                conn = &mut connecting => break conn,
+                frame = rx.recv() => match frame {
+                    Some(frame) => pending.push(frame),
+                    // the node is gone
+                    None => return,
+                },
+            }
+        };
+        // a frame cut short by the last connection is sent
+        // whole again; the protocol copes with duplicates
+        pending.written = 0;
+        let mut progress = Instant::now();
+        loop {
+            if pending.frames.is_empty() {
+                match rx.recv().await {
+                    Some(frame) => pending.push(frame),
+                    None => return,
+                }
+                progress = Instant::now();
+            }
+            // coalesce whatever else was queued in the meantime
+            while let Ok(frame) = rx.try_recv() {
+                pending.push(frame);
+            }
+            let slices = pending.slices();
+            let write = tokio::time::timeout_at(progress + WRITE_TIMEOUT, conn.write_vectored(&slices));
+            tokio::select! {
+                written = write => match written {
+                    Ok(Ok(n)) if n > 0 => {
+                        pending.advance(n);
+                        progress = Instant::now();
+                    },
+                    // failed, timed out or closed
+                    _ => break,
+                },
+                // a slow peer doesn't hold the queue up
+                frame = rx.recv() => match frame {
+                    Some(frame) => pending.push(frame),
+                    None => return,
+                },
+            }
+        }
+        let m = Message::Error(ErrorKind::DisconnectedTx(id));
+        tx.send(m).await.unwrap_or(());
+    }
+}
+
+impl Pending {
+    fn push(&mut self, frame: Frame) {
+        if self.frames.len() >= MAX_PENDING {
+            // the rest of a frame cut short must still follow
+            let oldest = if self.written > 0 { 1 } else { 0 };
+            self.frames.remove(oldest);
+        }
+        self.frames.push_back(frame);
+    }
+
+    fn slices(&self) -> Vec<IoSlice<'_>> {
+        self.frames
+            .iter()
+            .take(MAX_IOVECS)
+            .enumerate()
+            .map(|(i, frame)| IoSlice::new(if i == 0 { &frame[self.written..] } else { frame }))
+            .collect()
+    }
+
+    fn advance(&mut self, mut n: usize) {
+        while let Some(frame) = self.frames.front() {
+            let left = frame.len() - self.written;
+            if n < left {
+                self.written += n;
+                return;
+            }
+            n -= left;
+            self.written = 0;
+            self.frames.pop_front();
+        }
+    }
+}
+
+async fn establish(me: u32, addr: SocketAddr) -> TcpStream {
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        if let Ok(mut conn) = TcpStream::connect(addr).await {
+            conn.set_nodelay(true).unwrap_or(());
+            if conn.write_u32(me).await.is_ok() {
+                return conn;
+            }
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
@@ -0,0 +1,502 @@
+//! A deterministic, single threaded simulation of a cluster.
+//!
+//! Replicas run the same `System` code as over TCP, but their
+//! messages, timers and replies go through an in-memory event queue,
+//! driven by a virtual clock. Every random choice comes from a seeded
+//! generator, so a run can be reproduced from its seed.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::app::{AppKind, Counter};
+use crate::auth::{self, AuthConfig, Authenticator};
+use crate::config::Config;
+use crate::fault::Fault;
+use crate::log::{Decision, Digest};
+use crate::metrics::MetricsConfig;
+use crate::message::{Message, ReplyMessage, RequestMessage, Signed, SystemMessage};
+use crate::node::Network;
+use crate::system::System;
+use crate::util::Prng;
+
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    pub seed: u64,
+    pub n: u32,
+    pub f: u32,
+    /// Closed loop clients, each waiting for `f + 1` matching
+    /// replies before submitting its next request.
+    pub clients: u32,
+    pub requests_per_client: u32,
+    /// Delay of each message, picked uniformly from this range.
+    pub min_delay: Duration,
+    pub max_delay: Duration,
+    /// Probability of holding a message back for up to ten times
+    /// `max_delay`, so it gets overtaken by later messages.
+    pub reorder: f64,
+    /// Probability of losing a message between two replicas.
+    pub drop: f64,
+    /// Replica that stops responding, and when.
+    pub crash: Option<(u32, Duration)>,
+    /// Replica whose messages are all lost in between two
+    /// points in time, after which it should catch up.
+    pub isolate: Option<(u32, Duration, Duration)>,
+    /// Replicas that misbehave; the invariants are only
+    /// checked on the others.
+    pub faults: Vec<(u32, Fault)>,
+    /// Virtual time at which the run is stopped.
+    pub max_time: Duration,
+    pub timeout: Duration,
+    pub checkpoint_period: i32,
+    pub batch_size: usize,
+    pub batch_timeout: Duration,
+    pub window: i32,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            seed: 0,
+            n: 4,
+            f: 1,
+            clients: 3,
+            requests_per_client: 20,
+            min_delay: Duration::from_micros(100),
+            max_delay: Duration::from_millis(2),
+            reorder: 0.0,
+            drop: 0.0,
+            crash: None,
+            isolate: None,
+            faults: Vec::new(),
+            max_time: Duration::from_secs(60),
+            timeout: Duration::from_millis(50),
+            checkpoint_period: 8,
+            batch_size: 4,
+            batch_timeout: Duration::from_millis(1),
+            window: 4,
+        }
+    }
+}
+
+/// A safety property that didn't hold among the honest replicas.
+#[derive(Debug, Clone)]
+pub enum Violation {
+    /// Two replicas decided different batches for `seq`.
+    Agreement { seq: i32, first: (u32, Digest), second: (u32, Digest) },
+    /// A replica skipped or repeated a sequence number.
+    Order { replica: u32, expected: i32, got: i32 },
+    /// Replicas executed a request on different instances.
+    Reply { client: u32, id: u32, first: i32, second: i32 },
+    /// Replicas executed a request on the same instance, but
+    /// their application replied differently.
+    Result { client: u32, id: u32, seq: i32 },
+}
+
+/// Summary of a run without violations.
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// Requests that gathered `f + 1` matching replies.
+    pub completed: u32,
+    /// Number of instances decided by each replica.
+    pub decided: Vec<i32>,
+    /// Virtual time when the last event was processed.
+    pub elapsed: Duration,
+}
+
+#[derive(Debug)]
+enum Effect {
+    Send(u32, SystemMessage),
+    Reply(ReplyMessage),
+    Timer(Duration, Message),
+    Decided(Decision),
+    Restored(i32),
+}
+
+#[derive(Debug)]
+struct Shared {
+    start: Instant,
+    now: Cell<Duration>,
+    effects: RefCell<Vec<(u32, Effect)>>,
+}
+
+/// The in-memory `Network` of a simulated replica.
+#[derive(Debug)]
+pub struct SimNode {
+    id: u32,
+    n: u32,
+    fault: Option<Fault>,
+    auth: Box<dyn Authenticator>,
+    shared: Rc<Shared>,
+}
+
+impl SimNode {
+    fn push(&self, effect: Effect) {
+        self.shared.effects.borrow_mut().push((self.id, effect));
+    }
+
+    // `m` in the frame it would be sent in over TCP,
+    // if it may be relayed
+    fn framed(&self, mut m: SystemMessage) -> SystemMessage {
+        match m {
+            SystemMessage::Consensus(ref c) if c.kind.relayed() => {
+                let frame = Signed::new(&m, &*self.auth);
+                if let SystemMessage::Consensus(ref mut m) = m {
+                    m.frame = Some(frame);
+                }
+                m
+            },
+            m => m,
+        }
+    }
+}
+
+impl Network for SimNode {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn broadcast(&self, m: SystemMessage, targets: impl Iterator<Item = u32>) {
+        let mut framed = None;
+        for id in targets {
+            let m = match self.fault {
+                Some(fault) if id != self.id => match fault.tamper(&m, id, self.n, &*self.auth) {
+                    Some(m) => self.framed(m),
+                    None => continue,
+                },
+                _ => framed.get_or_insert_with(|| self.framed(m.clone())).clone(),
+            };
+            self.push(Effect::Send(id, m));
+        }
+    }
+
+    fn reply(&self, m: ReplyMessage) {
+        if self.fault.is_some_and(|fault| !fault.replies()) {
+            return;
+        }
+        self.push(Effect::Reply(m));
+    }
+
+    fn timeout(&self, after: Duration, timer: u64) {
+        self.push(Effect::Timer(after, Message::Timeout(timer)));
+    }
+
+    fn wake_up(&self, after: Duration) {
+        self.push(Effect::Timer(after, Message::BatchTimeout));
+    }
+
+    fn now(&self) -> Instant {
+        self.shared.start + self.shared.now.get()
+    }
+
+    fn auth(&self) -> &dyn Authenticator {
+        &*self.auth
+    }
+
+    fn decided(&self, decision: &Decision) {
+        self.push(Effect::Decided(decision.clone()));
+    }
+
+    fn restored(&self, seq: i32) {
+        self.push(Effect::Restored(seq));
+    }
+}
+
+#[derive(Debug)]
+enum Event {
+    Deliver(u32, Message),
+    Reply(u32, ReplyMessage),
+}
+
+#[derive(Debug, Default)]
+struct ClientState {
+    next_id: u32,
+    // replicas that agree on each reply to the pending request
+    votes: HashMap<(i32, Vec<u8>), Vec<u32>>,
+}
+
+pub struct Simulator {
+    cfg: SimConfig,
+    prng: Prng,
+    shared: Rc<Shared>,
+    replicas: Vec<System<SimNode>>,
+    // ordered by delivery time, then by scheduling order
+    events: BTreeMap<(Duration, u64), Event>,
+    scheduled: u64,
+    clients: Vec<ClientState>,
+    completed: u32,
+    // first replica to decide each instance, and on what
+    decided: BTreeMap<i32, (u32, Digest)>,
+    executed: Vec<i32>,
+    replied: HashMap<(u32, u32), ReplyMessage>,
+}
+
+impl Simulator {
+    pub fn new(cfg: SimConfig) -> Self {
+        let shared = Rc::new(Shared {
+            start: Instant::now(),
+            now: Cell::new(Duration::ZERO),
+            effects: RefCell::new(Vec::new()),
+        });
+        // only the number of addresses matters
+        let addrs: Vec<SocketAddr> = (0..cfg.n)
+            .map(|i| SocketAddr::from(([127, 0, 0, 1], i as u16)))
+            .collect();
+        // each pair of replicas shares a key no other replica
+        // has, so a faulty one can't speak for the others
+        let mut prng = Prng::new(cfg.seed);
+        let mut pair_keys = HashMap::new();
+        for i in 0..cfg.n {
+            for j in i..cfg.n {
+                let key: Vec<u8> = (0..8).flat_map(|_| prng.next().unwrap().to_be_bytes()).collect();
+                pair_keys.insert((i, j), key);
+            }
+        }
+        let replicas = (0..cfg.n)
+            .map(|id| {
+                let fault = cfg.faults
+                    .iter()
+                    .find(|&&(faulty, _)| faulty == id)
+                    .map(|&(_, fault)| fault);
+                let keys = (0..cfg.n)
+                    .map(|other| pair_keys[&(id.min(other), id.max(other))].clone())
+                    .collect();
+                let auth = auth::hmac_vector(id, keys);
+                let node = SimNode { id, n: cfg.n, fault, auth, shared: Rc::clone(&shared) };
+                let config = Config {
+                    id,
+                    f: cfg.f,
+                    addrs: addrs.clone(),
+                    listen: addrs[id as usize],
+                    client_listen: addrs[id as usize],
+                    timeout: cfg.timeout,
+                    checkpoint_period: cfg.checkpoint_period,
+                    batch_size: cfg.batch_size,
+                    batch_timeout: cfg.batch_timeout,
+                    window: cfg.window,
+                    // the keys are handed to the `SimNode`
+                    auth: AuthConfig::default(),
+                    app: AppKind::Counter,
+                    wal: None,
+                    // injected by the `SimNode`
+                    fault: None,
+                    metrics: MetricsConfig::default(),
+                };
+                System::new(config, node).unwrap()
+            })
+            .collect();
+        let clients = (0..cfg.clients)
+            .map(|_| ClientState::default())
+            .collect();
+        Simulator {
+            prng: Prng::new(cfg.seed),
+            executed: vec![0; cfg.n as usize],
+            cfg,
+            shared,
+            replicas,
+            events: BTreeMap::new(),
+            scheduled: 0,
+            clients,
+            completed: 0,
+            decided: BTreeMap::new(),
+            replied: HashMap::new(),
+        }
+    }
+
+    /// Run until there is nothing left to do, or until `max_time`.
+    pub fn run(mut self) -> Result<Report, Violation> {
+        for client in 0..self.cfg.clients {
+            self.submit(client);
+        }
+        while let Some(((time, _), event)) = self.events.pop_first() {
+            if time > self.cfg.max_time {
+                break;
+            }
+            self.shared.now.set(time);
+            match event {
+                Event::Deliver(to, _) if self.crashed(to) => (),
+                Event::Deliver(to, message) => {
+                    let sys = &mut self.replicas[to as usize];
+                    sys.handle(message);
+                    while let Some(message) = sys.poll() {
+                        sys.handle(message);
+                    }
+                },
+                Event::Reply(from, reply) => self.receive_reply(from, reply)?,
+            }
+            self.flush()?;
+        }
+        Ok(Report {
+            completed: self.completed,
+            decided: self.executed,
+            elapsed: self.shared.now.get(),
+        })
+    }
+
+    fn honest(&self, id: u32) -> bool {
+        self.cfg.faults.iter().all(|&(faulty, _)| faulty != id)
+    }
+
+    // how long a faulty replica holds its messages back
+    fn held_back(&self, id: u32) -> Duration {
+        self.cfg.faults
+            .iter()
+            .filter(|&&(faulty, _)| faulty == id)
+            .find_map(|(_, fault)| fault.delay())
+            .unwrap_or_default()
+    }
+
+    fn crashed(&self, id: u32) -> bool {
+        self.cfg.crash
+            .is_some_and(|(crashed, at)| crashed == id && self.shared.now.get() >= at)
+    }
+
+    fn isolated(&self, id: u32) -> bool {
+        let now = self.shared.now.get();
+        self.cfg.isolate
+            .is_some_and(|(isolated, from, until)| isolated == id && now >= from && now < until)
+    }
+
+    fn schedule(&mut self, after: Duration, event: Event) {
+        let at = self.shared.now.get() + after;
+        self.events.insert((at, self.scheduled), event);
+        self.scheduled += 1;
+    }
+
+    // how long a message takes to arrive, if it does
+    fn link_delay(&mut self) -> Option<Duration> {
+        if self.prng.unit() < self.cfg.drop {
+            return None;
+        }
+        let mut delay = self.prng.range(self.cfg.min_delay, self.cfg.max_delay);
+        if self.prng.unit() < self.cfg.reorder {
+            delay += self.prng.range(Duration::ZERO, self.cfg.max_delay * 10);
+        }
+        Some(delay)
+    }
+
+    fn submit(&mut self, client: u32) {
+        let state = &mut self.clients[client as usize];
+        if state.next_id >= self.cfg.requests_per_client {
+            return;
+        }
+        let request = RequestMessage {
+            client,
+            id: state.next_id,
+            op: Counter::op((client * self.cfg.requests_per_client + state.next_id) as i32),
+        };
+        state.votes.clear();
+        for to in 0..self.cfg.n {
+            let message = Message::System(SystemMessage::Request(request.clone()));
+            if self.isolated(to) {
+                continue;
+            }
+            if let Some(delay) = self.link_delay() {
+                self.schedule(delay, Event::Deliver(to, message));
+            }
+        }
+    }
+
+    fn receive_reply(&mut self, from: u32, reply: ReplyMessage) -> Result<(), Violation> {
+        // the client itself copes with faulty replies
+        let first = if self.honest(from) {
+            self.replied.insert((reply.client, reply.id), reply.clone())
+        } else {
+            None
+        };
+        match first {
+            Some(first) if first.seq != reply.seq => {
+                return Err(Violation::Reply {
+                    client: reply.client,
+                    id: reply.id,
+                    first: first.seq,
+                    second: reply.seq,
+                });
+            },
+            Some(first) if first.result != reply.result => {
+                return Err(Violation::Result {
+                    client: reply.client,
+                    id: reply.id,
+                    seq: reply.seq,
+                });
+            },
+            _ => (),
+        }
+        let state = &mut self.clients[reply.client as usize];
+        if reply.id != state.next_id {
+            return Ok(());
+        }
+        let voters = state.votes.entry((reply.seq, reply.result)).or_default();
+        if !voters.contains(&from) {
+            voters.push(from);
+        }
+        if voters.len() as u32 > self.cfg.f {
+            state.next_id += 1;
+            self.completed += 1;
+            self.submit(reply.client);
+        }
+        Ok(())
+    }
+
+    // all replicas must decide the same batch on each instance,
+    // checked as they decide it, before their log is truncated
+    fn check_decision(&mut self, id: u32, d: Decision) -> Result<(), Violation> {
+        let executed = &mut self.executed[id as usize];
+        if d.seq != *executed {
+            return Err(Violation::Order { replica: id, expected: *executed, got: d.seq });
+        }
+        *executed += 1;
+        match self.decided.get(&d.seq) {
+            None => {
+                self.decided.insert(d.seq, (id, d.digest));
+            },
+            Some(&first) if first.1 != d.digest => {
+                return Err(Violation::Agreement {
+                    seq: d.seq,
+                    first,
+                    second: (id, d.digest),
+                });
+            },
+            Some(_) => (),
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Violation> {
+        let effects = std::mem::take(&mut *self.shared.effects.borrow_mut());
+        for (from, effect) in effects {
+            if self.crashed(from) {
+                continue;
+            }
+            match effect {
+                Effect::Send(to, m) if to == from => {
+                    self.schedule(Duration::ZERO, Event::Deliver(to, Message::System(m)));
+                },
+                Effect::Send(to, _) if self.isolated(from) || self.isolated(to) => (),
+                Effect::Send(to, m) => {
+                    if let Some(delay) = self.link_delay() {
+                        let delay = delay + self.held_back(from);
+                        self.schedule(delay, Event::Deliver(to, Message::System(m)));
+                    }
+                },
+                Effect::Reply(_) if self.isolated(from) => (),
+                Effect::Reply(m) => {
+                    if let Some(delay) = self.link_delay() {
+                        let delay = delay + self.held_back(from);
+                        self.schedule(delay, Event::Reply(from, m));
+                    }
+                },
+                Effect::Timer(after, m) => {
+                    self.schedule(after, Event::Deliver(from, m));
+                },
+                Effect::Decided(d) if self.honest(from) => self.check_decision(from, d)?,
+                Effect::Decided(_) => (),
+                // the instances before the checkpoint are skipped
+                Effect::Restored(seq) => self.executed[from as usize] = seq + 1,
+            }
+        }
+        Ok(())
+    }
+}
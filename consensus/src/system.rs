@@ -0,0 +1,870 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::io;
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::app::{self, Application};
+use crate::auth;
+use crate::cert::{Certificates, Rejected};
+use crate::config::Config;
+use crate::log::{self, batch_digest, Checkpoint, Decision, Digest, Log, Snapshot};
+use crate::metrics::Metrics;
+use crate::message::{
+    ConsensusMessage,
+    ConsensusMessageKind,
+    ErrorKind,
+    Message,
+    ReplyMessage,
+    RequestMessage,
+    Signed,
+    SystemMessage,
+};
+use crate::node::{Network, Node};
+use crate::transfer::{Offer, StateTransfer, Verified};
+use crate::view::{self, Prepared, ViewChanges};
+use crate::wal::{Wal, WalEntry};
+
+/// Where a consensus instance is at, once we accepted its proposal.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ProtoPhase {
+    /// Waiting for a quorum of prepares.
+    Preparing,
+    /// Prepared, waiting for a quorum of commits.
+    Commiting,
+    /// Decided, waiting for the instances before it to be executed.
+    Executing,
+}
+
+#[derive(Debug)]
+struct Instance {
+    phase: ProtoPhase,
+    batch: Vec<RequestMessage>,
+    digest: Digest,
+}
+
+#[derive(Debug)]
+pub struct System<N = Node> {
+    // the next instance to execute
+    seq: i32,
+    // the next instance the leader proposes
+    next: i32,
+    view: u32,
+    n: u32,
+    f: u32,
+    // most instances the leader runs at once
+    window: i32,
+    batch_size: usize,
+    batch_timeout: Duration,
+    batch_deadline: Option<Instant>,
+    // the last reply sent to each client
+    replies: HashMap<u32, ReplyMessage>,
+    // the instances between the watermarks whose
+    // proposal we accepted, and haven't executed yet
+    instances: BTreeMap<i32, Instance>,
+    // values prepared after the last checkpoint we can prove
+    // is stable, whether we executed them yet or not
+    prepared: BTreeMap<i32, Prepared>,
+    certs: Certificates,
+    view_changes: ViewChanges,
+    log: Log,
+    app: Box<dyn Application>,
+    // our state at the checkpoints since the last stable one
+    snapshots: BTreeMap<i32, Snapshot>,
+    transfer: StateTransfer,
+    wal: Option<Wal>,
+    node: N,
+    requests: VecDeque<RequestMessage>,
+    // messages to handle again, now that they can be accounted for
+    ready: VecDeque<ConsensusMessage>,
+    // messages past the high watermark, kept for when it moves
+    ahead: Vec<ConsensusMessage>,
+    metrics: Metrics,
+}
+
+impl System<Node> {
+    pub async fn boot(cfg: Config) -> io::Result<Self> {
+        check_config(&cfg)?;
+        let n = cfg.addrs.len() as u32;
+        let auth = Arc::from(auth::new(&cfg.auth, cfg.id, n)?);
+        let node = Node::bootstrap(cfg.id, cfg.addrs.clone(), cfg.listen, cfg.client_listen, auth, cfg.fault).await?;
+        let wal = cfg.wal.clone();
+        let mut sys = System::new(cfg, node)?;
+        if let Some(wal) = wal {
+            let (wal, entries) = Wal::open(wal)?;
+            sys.recover(entries)?;
+            sys.wal = Some(wal);
+        }
+        Ok(sys)
+    }
+
+    #[inline]
+    pub async fn replica_loop(&mut self) -> io::Result<()> {
+        // TODO:
+        //  - handle errors
+        let shutdown = shutdown();
+        tokio::pin!(shutdown);
+        loop {
+            let message = match self.poll() {
+                Some(message) => message,
+                None => tokio::select! {
+                    message = self.node.receive() => message?,
+                    signal = &mut shutdown => {
+                        signal?;
+                        return self.metrics.finish(self.node.now());
+                    },
+                },
+            };
+            match message {
+                Message::ConnectedClient(client, conn) => {
+                    self.node.client_connected(client, conn);
+                },
+                Message::Error(ErrorKind::DisconnectedClient(client)) => {
+                    self.node.client_disconnected(client);
+                },
+                message => self.handle(message),
+            }
+        }
+    }
+}
+
+impl<N: Network> System<N> {
+    pub fn new(cfg: Config, node: N) -> io::Result<Self> {
+        check_config(&cfg)?;
+        let app = app::new(cfg.app);
+        // what we offer in state transfers, until
+        // a checkpoint becomes stable
+        let initial = Snapshot {
+            seq: -1,
+            log: [0; 32],
+            state: app.snapshot(),
+            replies: Vec::new(),
+        };
+        Ok(System {
+            n: cfg.addrs.len() as u32,
+            f: cfg.f,
+            seq: 0,
+            next: 0,
+            view: 0,
+            window: cfg.window,
+            batch_size: cfg.batch_size,
+            batch_timeout: cfg.batch_timeout,
+            batch_deadline: None,
+            replies: HashMap::new(),
+            instances: BTreeMap::new(),
+            prepared: BTreeMap::new(),
+            certs: Certificates::default(),
+            view_changes: ViewChanges::new(cfg.timeout),
+            log: Log::new(cfg.checkpoint_period),
+            app,
+            snapshots: BTreeMap::from([(-1, initial)]),
+            transfer: StateTransfer::default(),
+            wal: None,
+            requests: VecDeque::new(),
+            ready: VecDeque::new(),
+            metrics: Metrics::new(cfg.id, cfg.metrics.clone(), node.now()),
+            ahead: Vec::new(),
+            node,
+        })
+    }
+
+    fn quorum(&self) -> u32 {
+        2*self.f + 1
+    }
+
+    /// Make progress without waiting for the network, by taking a
+    /// message that can be handled now, or proposing batches while
+    /// the window has room. Returns `None` once we need a new message.
+    pub fn poll(&mut self) -> Option<Message> {
+        if let Some(m) = self.ready.pop_front() {
+            return Some(Message::System(SystemMessage::Consensus(m)));
+        }
+        while self.can_propose() {
+            self.propose_batch();
+        }
+        None
+    }
+
+    pub fn handle(&mut self, message: Message) {
+        match message {
+            Message::System(message) => {
+                match message {
+                    SystemMessage::Request(message) => {
+                        self.process_request(message);
+                    },
+                    SystemMessage::Consensus(message) => {
+                        self.process_consensus(message);
+                    },
+                    // ....
+                }
+            },
+            Message::Timeout(timer) => {
+                if timer == self.view_changes.timer && self.view_changes.armed {
+                    self.view_changes.armed = false;
+                    self.timed_out();
+                }
+            },
+            // checked in the next poll
+            Message::BatchTimeout => (),
+            Message::Error(e) => {
+                // a crashed leader will be replaced
+                // by the view change protocol
+                eprintln!("Replica r{} got {:?}", self.node.id(), e);
+            },
+            // ....
+            m => panic!("{:?}", m),
+        };
+    }
+
+    fn process_request(&mut self, message: RequestMessage) {
+        match self.replies.get(&message.client) {
+            // the reply may have been lost
+            Some(reply) if reply.id == message.id => self.node.reply(reply.clone()),
+            Some(reply) if reply.id > message.id => (),
+            _ => {
+                self.metrics.received(&message, self.node.now());
+                self.requests.push_back(message);
+                self.arm_batch_timeout();
+                self.update_timer();
+            },
+        }
+    }
+
+    fn executed(&self, request: &RequestMessage) -> bool {
+        self.replies
+            .get(&request.client)
+            .is_some_and(|reply| reply.id >= request.id)
+    }
+
+    fn can_propose(&self) -> bool {
+        let leader = self.leader() == self.node.id();
+        let room = self.next < self.seq + self.window && self.next < self.log.high_watermark();
+        leader && room && self.view_changes.pending.is_none() && self.batch_ready()
+    }
+
+    fn batch_ready(&self) -> bool {
+        let timed_out = self.batch_deadline
+            .is_some_and(|deadline| self.node.now() >= deadline);
+        self.requests.len() >= self.batch_size || (timed_out && !self.requests.is_empty())
+    }
+
+    fn arm_batch_timeout(&mut self) {
+        if self.batch_deadline.is_some() || self.leader() != self.node.id() {
+            return;
+        }
+        self.batch_deadline = Some(self.node.now() + self.batch_timeout);
+        self.node.wake_up(self.batch_timeout);
+    }
+
+    #[inline]
+    fn propose_batch(&mut self) {
+        let len = self.requests.len().min(self.batch_size);
+        let batch = self.requests.drain(..len).collect();
+        self.batch_deadline = None;
+        if !self.requests.is_empty() {
+            self.arm_batch_timeout();
+        }
+        let message = self.new_consensus_msg(self.next, ConsensusMessageKind::PrePrepare(batch));
+        self.node.broadcast(message, 0_u32..self.n);
+        self.next += 1;
+    }
+
+    #[inline]
+    fn process_consensus(&mut self, mut message: ConsensusMessage) {
+        // with instances running concurrently, only these
+        // tell how far the sender got in executing them
+        let executed = match message.kind {
+            ConsensusMessageKind::Checkpoint(_) => Some(message.seq + 1),
+            ConsensusMessageKind::ViewChange(..) | ConsensusMessageKind::FetchState => Some(message.seq),
+            _ => None,
+        };
+        if let Some(seq) = executed {
+            self.transfer.progress(message.from, seq);
+        }
+        match message.kind {
+            ConsensusMessageKind::ViewChange(..) => return self.process_view_change(message),
+            ConsensusMessageKind::NewView(_) => return self.process_new_view(message),
+            ConsensusMessageKind::FetchState => return self.send_state(message.from, message.seq),
+            ConsensusMessageKind::State(..) => return self.process_state(message),
+            _ if message.seq >= self.log.high_watermark() => {
+                // too far ahead of the last stable checkpoint; the
+                // instances in between can only be fetched, but the
+                // ones right after them are kept for after the jump,
+                // since the others won't send them again
+                let high = self.log.high_watermark();
+                let window = high - self.log.low_watermark();
+                let checkpoint = matches!(message.kind, ConsensusMessageKind::Checkpoint(_));
+                if message.seq < high + window && !checkpoint {
+                    self.ahead.push(message);
+                }
+                return self.fetch_state();
+            },
+            ConsensusMessageKind::Checkpoint(digest) => {
+                let frame = message.take_frame();
+                return self.process_checkpoint(message.seq, message.from, digest, frame);
+            },
+            _ => (),
+        }
+        if message.seq < self.seq {
+            // we executed that instance already
+            return;
+        }
+        if message.view > self.view {
+            // we haven't installed this view yet
+            self.view_changes.future.push(message);
+            return;
+        }
+        let is_commit = matches!(message.kind, ConsensusMessageKind::Commit(_));
+        if message.view < self.view && !(is_commit && message.seq < self.view_changes.start) {
+            // commits from older views are only useful to
+            // finish the instances decided before this view
+            return;
+        }
+        if self.view_changes.pending.is_some() && !is_commit {
+            // stop taking part in the current view
+            return;
+        }
+        let (seq, from, frame) = (message.seq, message.from, message.take_frame());
+        let voted = match message.kind {
+            ConsensusMessageKind::PrePrepare(batch) => return self.process_pre_prepare(seq, from, batch),
+            ConsensusMessageKind::Prepare(digest) => self.certs.prepare(seq, from, digest, frame),
+            ConsensusMessageKind::Commit(digest) => self.certs.commit(seq, from, digest),
+            _ => return,
+        };
+        match voted {
+            Ok(()) => self.advance(seq),
+            Err(reason) => self.rejected(reason),
+        }
+    }
+
+    fn process_pre_prepare(&mut self, seq: i32, from: u32, batch: Vec<RequestMessage>) {
+        // only the leader may propose values, and
+        // only on the instances of its own view
+        if from != self.leader() || seq < self.view_changes.start {
+            return;
+        }
+        let digest = batch_digest(&batch);
+        if let Err(reason) = self.certs.pre_prepare(seq, from, digest) {
+            // a retransmission, or proof that the leader is faulty
+            self.rejected(reason);
+            return;
+        }
+        if self.wal.is_some() {
+            self.persist(WalEntry::PrePrepare { seq, view: self.view, batch: batch.clone() });
+        }
+        // the leader's vote too must be proven in view changes
+        let message = self.new_consensus_msg(seq, ConsensusMessageKind::Prepare(digest));
+        self.node.broadcast(message, 0_u32..self.n);
+        // a recovered leader resumes after its old proposals
+        self.next = self.next.max(seq + 1);
+        self.metrics.proposed(seq, &batch, self.node.now());
+        self.instances.insert(seq, Instance { phase: ProtoPhase::Preparing, batch, digest });
+        self.update_timer();
+        self.advance(seq);
+    }
+
+    // move an instance on once it gathered enough votes,
+    // some of which may have arrived before its proposal
+    fn advance(&mut self, seq: i32) {
+        let quorum = self.quorum();
+        let instance = match self.instances.get_mut(&seq) {
+            Some(instance) => instance,
+            None => return,
+        };
+        let digest = instance.digest;
+        if instance.phase == ProtoPhase::Preparing && self.certs.prepares(seq) >= quorum {
+            instance.phase = ProtoPhase::Commiting;
+            self.metrics.prepared(seq, self.node.now());
+            let proof = self.certs.prepare_proof(seq);
+            let prepared = Prepared { seq, view: self.view, batch: instance.batch.clone(), proof };
+            self.prepared.insert(seq, prepared);
+            let message = ConsensusMessage::new(self.node.id(), seq, self.view, ConsensusMessageKind::Commit(digest));
+            self.node.broadcast(SystemMessage::Consensus(message), 0_u32..self.n);
+        }
+        // a quorum of commits decides the value, even
+        // if some of the prepares never got to us
+        if instance.phase != ProtoPhase::Executing && self.certs.commits(seq) >= quorum {
+            instance.phase = ProtoPhase::Executing;
+            self.metrics.committed(seq, self.node.now());
+            self.persist(WalEntry::Commit { seq, view: self.view, digest });
+            self.execute_ready();
+        }
+    }
+
+    // execute the decided instances, in order
+    fn execute_ready(&mut self) {
+        while self.instances.get(&self.seq).is_some_and(|i| i.phase == ProtoPhase::Executing) {
+            let instance = self.instances.remove(&self.seq).unwrap();
+            self.execute(instance.batch, instance.digest);
+        }
+    }
+
+    fn rejected(&mut self, reason: Rejected) {
+        if let Rejected::Equivocation(e) = reason {
+            eprintln!(
+                "Equivocation detected on r{}: r{} proposed {} and {} for seq {} ({} so far)",
+                self.node.id(),
+                e.from,
+                log::short(&e.first),
+                log::short(&e.second),
+                e.seq,
+                self.certs.evidence().len(),
+            );
+            self.start_view_change(self.view + 1);
+        }
+    }
+
+    fn execute(&mut self, batch: Vec<RequestMessage>, digest: Digest) {
+        for request in batch.iter() {
+            // the leader may propose a request again
+            // after a view change
+            if self.executed(request) {
+                continue;
+            }
+            let reply = ReplyMessage {
+                client: request.client,
+                id: request.id,
+                seq: self.seq,
+                result: self.app.execute(&request.op),
+            };
+            self.replies.insert(request.client, reply.clone());
+            self.node.reply(reply);
+        }
+        let requests = std::mem::take(&mut self.requests);
+        self.requests = requests
+            .into_iter()
+            .filter(|r| !self.executed(r))
+            .collect();
+        self.metrics.executed(self.seq, &batch, self.node.now());
+        let decision = Decision { seq: self.seq, digest, batch };
+        self.node.decided(&decision);
+        if let Some(log) = self.log.decide(decision) {
+            let snapshot = self.take_snapshot(log);
+            let digest = snapshot.digest();
+            self.log.checkpointed(self.seq, digest);
+            self.snapshots.insert(self.seq, snapshot);
+            let message = self.new_consensus_msg(self.seq, ConsensusMessageKind::Checkpoint(digest));
+            self.node.broadcast(message, 0_u32..self.n);
+        }
+        self.seq += 1;
+        self.next = self.next.max(self.seq);
+        self.certs.discard_below(self.seq);
+        self.view_changes.reset_timeout();
+        self.stop_timer();
+        self.update_timer();
+    }
+
+    fn process_checkpoint(&mut self, seq: i32, from: u32, digest: Digest, frame: Signed) {
+        let checkpoint = self.log.checkpoint(seq, from, digest, self.quorum(), frame);
+        self.settle_checkpoint(checkpoint);
+    }
+
+    fn settle_checkpoint(&mut self, checkpoint: Checkpoint) {
+        match checkpoint {
+            Checkpoint::Pending => (),
+            Checkpoint::Stable(seq) => {
+                eprintln!("Stable checkpoint on r{} at seq {}", self.node.id(), seq);
+                let low = self.log.low_watermark();
+                self.certs.discard_below(low);
+                self.view_changes.future.retain(|m| m.seq >= low);
+                self.snapshots = self.snapshots.split_off(&seq);
+                // only a checkpoint we can prove stable lets
+                // the others skip the values prepared before it
+                if let Some(&(proven, ..)) = self.log.proven() {
+                    self.prepared = self.prepared.split_off(&(proven + 1));
+                }
+                if let (Some(wal), Some(snapshot)) = (self.wal.as_mut(), self.snapshots.get(&seq)) {
+                    let head = [
+                        WalEntry::Checkpoint(snapshot.clone()),
+                        WalEntry::View { view: self.view, start: self.view_changes.start },
+                    ];
+                    wal.compact(&head, low).expect("failed to compact the write-ahead log");
+                }
+                self.release_ahead();
+            },
+            Checkpoint::Diverged(seq) => {
+                eprintln!("State of r{} diverged from a quorum at seq {}", self.node.id(), seq);
+            },
+        }
+    }
+
+    // the messages kept past the old high watermark
+    // may be within the new one
+    fn release_ahead(&mut self) {
+        let (seq, high) = (self.seq, self.log.high_watermark());
+        let (now, later) = std::mem::take(&mut self.ahead)
+            .into_iter()
+            .filter(|m| m.seq >= seq)
+            .partition(|m| m.seq < high);
+        self.ahead = later;
+        self.ready.extend::<Vec<_>>(now);
+    }
+
+    // whether the others decided instances we can't finish on our own
+    fn behind(&self) -> bool {
+        self.seq < self.view_changes.start
+            || self.transfer.behind(self.seq, self.f)
+            || self.certs.decided(self.seq, self.quorum()).is_some()
+    }
+
+    fn fetch_state(&mut self) {
+        let now = self.node.now();
+        let retry = self.transfer.requested
+            .is_none_or(|at| now >= at + self.view_changes.timeout);
+        if !retry {
+            return;
+        }
+        if self.transfer.requested.is_none() {
+            eprintln!("Replica r{} fell behind at seq {}, fetching the state of the others", self.node.id(), self.seq);
+        }
+        self.transfer.requested = Some(now);
+        let message = self.new_consensus_msg(self.seq, ConsensusMessageKind::FetchState);
+        let me = self.node.id();
+        self.node.broadcast(message, (0_u32..self.n).filter(move |&id| id != me));
+    }
+
+    // offer our last stable checkpoint to a replica that is behind it
+    fn send_state(&self, to: u32, seq: i32) {
+        if seq >= self.seq {
+            return;
+        }
+        let low = self.log.low_watermark();
+        let snapshot = match self.snapshots.get(&(low - 1)) {
+            Some(snapshot) => snapshot.clone(),
+            None => return,
+        };
+        let decisions = self.log.decisions().cloned().collect();
+        let kind = ConsensusMessageKind::State(Box::new(snapshot), decisions);
+        let message = ConsensusMessage::new(self.node.id(), self.view_changes.start, self.view, kind);
+        self.node.broadcast(SystemMessage::Consensus(message), std::iter::once(to));
+    }
+
+    fn process_state(&mut self, message: ConsensusMessage) {
+        if self.transfer.requested.is_none() {
+            return;
+        }
+        let (snapshot, decisions) = match message.kind {
+            ConsensusMessageKind::State(snapshot, decisions) => (*snapshot, decisions),
+            _ => return,
+        };
+        let view = (message.view, message.seq);
+        self.transfer.receive(message.from, Offer { snapshot, decisions, view });
+        match self.transfer.select(self.f) {
+            Some(verified) if verified.snapshot.seq >= self.seq => self.jump(verified),
+            Some(verified) if verified.decisions.last().is_some_and(|d| d.seq >= self.seq) => self.jump(verified),
+            // wait for the offers of replicas further ahead
+            _ => (),
+        }
+    }
+
+    // skip the instances we missed, by installing the state
+    // `f + 1` replicas agree on
+    fn jump(&mut self, verified: Verified) {
+        self.transfer.reset();
+        let Verified { snapshot, decisions, view } = verified;
+        if snapshot.seq >= self.seq {
+            eprintln!("Replica r{} jumping from seq {} to the checkpoint at {}", self.node.id(), self.seq, snapshot.seq);
+            if let Err(e) = self.restore_snapshot(snapshot.clone()) {
+                eprintln!("Replica r{} failed to restore a checkpoint: {}", self.node.id(), e);
+                return;
+            }
+            self.instances = self.instances.split_off(&self.seq);
+            self.certs.discard_below(self.seq);
+            self.view_changes.future.retain(|m| m.seq >= snapshot.seq);
+            self.stop_timer();
+            if let Some(ref mut wal) = self.wal {
+                let head = [
+                    WalEntry::Checkpoint(snapshot),
+                    WalEntry::View { view: self.view, start: self.view_changes.start },
+                ];
+                wal.compact(&head, self.seq).expect("failed to compact the write-ahead log");
+            }
+        } else {
+            // the checkpoint votes we missed won't be sent again
+            let checkpoint = self.log.vouched(snapshot.seq, snapshot.digest());
+            self.settle_checkpoint(checkpoint);
+        }
+        for d in decisions {
+            if d.seq != self.seq || d.seq >= self.log.high_watermark() {
+                continue;
+            }
+            if self.wal.is_some() {
+                self.persist(WalEntry::PrePrepare { seq: d.seq, view: self.view, batch: d.batch.clone() });
+                self.persist(WalEntry::Commit { seq: d.seq, view: self.view, digest: d.digest });
+            }
+            self.instances.remove(&d.seq);
+            self.execute(d.batch, d.digest);
+        }
+        // the instances after those may have been decided already
+        self.release_ahead();
+        self.execute_ready();
+        // the instances decided while the offers were on
+        // their way may not be sent to us again; if we
+        // don't hear about the next one in time, ask again
+        if self.behind() {
+            self.fetch_state();
+        }
+        self.start_timer();
+        // the others may have moved on to a later view
+        if let Some((view, start)) = view {
+            if view > self.view && self.view_changes.pending.is_none_or(|v| v <= view) {
+                self.install_view(view, start, Vec::new());
+            }
+        }
+    }
+
+    fn leader(&self) -> u32 {
+        self.view % self.n
+    }
+
+    fn start_timer(&mut self) {
+        self.view_changes.timer += 1;
+        self.view_changes.armed = true;
+        self.node.timeout(self.view_changes.timeout, self.view_changes.timer);
+    }
+
+    fn stop_timer(&mut self) {
+        self.view_changes.timer += 1;
+        self.view_changes.armed = false;
+    }
+
+    // the leader is suspected once it leaves work undone for a
+    // whole timeout, which only starts over when an instance is
+    // executed; without any work, there is nothing to wait for
+    fn update_timer(&mut self) {
+        if self.view_changes.pending.is_some() {
+            return;
+        }
+        let idle = self.instances.is_empty() && self.requests.is_empty();
+        if idle {
+            self.stop_timer();
+        } else if !self.view_changes.armed {
+            self.start_timer();
+        }
+    }
+
+    fn timed_out(&mut self) {
+        if self.behind() {
+            // the leader made progress without us, and the
+            // instances we missed may not be proposed again
+            self.fetch_state();
+            self.start_timer();
+            return;
+        }
+        let idle = self.instances.is_empty() && self.requests.is_empty();
+        if idle && self.view_changes.pending.is_none() {
+            // nothing to suspect the leader of
+            return;
+        }
+        let next = self.view_changes.pending.unwrap_or(self.view) + 1;
+        self.view_changes.timeout *= 2;
+        self.start_view_change(next);
+    }
+
+    fn start_view_change(&mut self, view: u32) {
+        if self.view_changes.pending.is_some_and(|v| v >= view) {
+            return;
+        }
+        eprintln!("Replica r{} moving to view {}", self.node.id(), view);
+        self.view_changes.pending = Some(view);
+        let checkpoint = match self.log.proven() {
+            Some((.., votes)) => votes.clone(),
+            None => Vec::new(),
+        };
+        let prepared = self.prepared.values().cloned().collect();
+        let kind = ConsensusMessageKind::ViewChange(checkpoint, prepared);
+        let message = ConsensusMessage::new(self.node.id(), self.seq, view, kind);
+        self.node.broadcast(SystemMessage::Consensus(message), 0_u32..self.n);
+        self.start_timer();
+    }
+
+    fn process_view_change(&mut self, message: ConsensusMessage) {
+        let view = message.view;
+        if view <= self.view || !view::valid_view_change(&message, view, self.n, self.quorum(), self.node.auth()) {
+            return;
+        }
+        let votes = self.view_changes.receive(message);
+        let joining = self.view_changes.pending.is_none_or(|v| v < view);
+        if joining && votes > self.f {
+            // at least one correct replica suspects the leader
+            self.start_view_change(view);
+        }
+        if view % self.n == self.node.id() && self.view_changes.pending == Some(view) {
+            if let Some(proof) = self.view_changes.take_proof(view, self.quorum()) {
+                let (start, _) = view::select(&proof, self.f);
+                // relayed as they were sent to us, so that the
+                // others can check them for themselves
+                let proof = proof
+                    .into_iter()
+                    .map(|mut m| m.take_frame())
+                    .collect();
+                let kind = ConsensusMessageKind::NewView(proof);
+                let message = ConsensusMessage::new(self.node.id(), start, view, kind);
+                self.node.broadcast(SystemMessage::Consensus(message), 0_u32..self.n);
+            }
+        }
+    }
+
+    fn process_new_view(&mut self, message: ConsensusMessage) {
+        let proof = match message.kind {
+            ConsensusMessageKind::NewView(ref proof) => proof,
+            _ => return,
+        };
+        if message.view <= self.view || message.from != message.view % self.n {
+            return;
+        }
+        let proof = match view::open_proof(proof, message.view, self.n, self.quorum(), self.node.auth()) {
+            Some(proof) => proof,
+            None => return,
+        };
+        let (start, proposals) = view::select(&proof, self.f);
+        if start != message.seq {
+            return;
+        }
+        self.install_view(message.view, start, proposals);
+    }
+
+    fn install_view(&mut self, view: u32, start: i32, proposals: Vec<Prepared>) {
+        eprintln!("Replica r{} installed view {} at seq {}", self.node.id(), view, start);
+        self.persist(WalEntry::View { view, start });
+        self.view = view;
+        self.view_changes.installed(view, start);
+        // the instances from `start` onwards are decided again,
+        // and the ones before it only need the commits sent
+        // in the previous views
+        self.certs.discard_from(start);
+        self.instances.retain(|&seq, _| seq < start);
+        self.metrics.discard_from(start);
+        self.next = start.max(self.seq);
+        self.stop_timer();
+
+        // we may be the new leader
+        self.batch_deadline = None;
+        if !self.requests.is_empty() {
+            self.arm_batch_timeout();
+        }
+
+        // the new leader proposes the prepared values again
+        let leader = self.leader();
+        for p in proposals {
+            self.next = self.next.max(p.seq + 1);
+            let kind = ConsensusMessageKind::PrePrepare(p.batch);
+            self.ready.push_back(ConsensusMessage::new(leader, p.seq, view, kind));
+        }
+
+        // replay the messages that arrived before the new view
+        let (current, future): (Vec<_>, _) = std::mem::take(&mut self.view_changes.future)
+            .into_iter()
+            .partition(|m| m.view == view);
+        self.view_changes.future = future;
+        self.ready.extend(current);
+        self.update_timer();
+    }
+
+    fn take_snapshot(&self, log: Digest) -> Snapshot {
+        let mut replies: Vec<_> = self.replies.values().cloned().collect();
+        replies.sort_unstable_by_key(|r| r.client);
+        Snapshot {
+            seq: self.seq,
+            log,
+            state: self.app.snapshot(),
+            replies,
+        }
+    }
+
+    fn restore_snapshot(&mut self, snapshot: Snapshot) -> io::Result<()> {
+        self.app.restore(&snapshot.state)?;
+        self.replies = snapshot.replies
+            .iter()
+            .map(|r| (r.client, r.clone()))
+            .collect();
+        let replies = &self.replies;
+        self.metrics.retain_requests(|client, id| replies.get(&client).is_none_or(|r| r.id < id));
+        self.log.restore(snapshot.seq, snapshot.log);
+        self.node.restored(snapshot.seq);
+        self.seq = snapshot.seq + 1;
+        self.next = self.next.max(self.seq);
+        self.snapshots.insert(snapshot.seq, snapshot);
+        Ok(())
+    }
+
+    // a replica that can't persist its decisions
+    // can't safely take part in the protocol
+    fn persist(&mut self, entry: WalEntry) {
+        if let Some(ref mut wal) = self.wal {
+            wal.append(&entry).expect("failed to write to the write-ahead log");
+        }
+    }
+
+    /// Rebuild the state persisted in a write-ahead log: re-execute
+    /// the committed instances, and resume the one in progress.
+    fn recover(&mut self, entries: Vec<WalEntry>) -> io::Result<()> {
+        let mut accepted = BTreeMap::new();
+        let mut committed = BTreeMap::new();
+        for entry in entries {
+            match entry {
+                WalEntry::Checkpoint(snapshot) => self.restore_snapshot(snapshot)?,
+                WalEntry::View { view, start } => {
+                    self.view = view;
+                    self.view_changes.installed(view, start);
+                },
+                // a proposal from a later view replaces the previous one
+                WalEntry::PrePrepare { seq, view, batch } => {
+                    accepted.insert(seq, (view, batch));
+                },
+                WalEntry::Commit { seq, digest, .. } => {
+                    committed.insert(seq, digest);
+                },
+            }
+        }
+        // execute the committed instances again, and accept the
+        // other proposals of the current view as if the leader
+        // had just sent them; older ones are dropped
+        let leader = self.leader();
+        for (seq, (view, batch)) in accepted.split_off(&self.seq) {
+            let digest = batch_digest(&batch);
+            if committed.get(&seq) == Some(&digest) {
+                self.instances.insert(seq, Instance { phase: ProtoPhase::Executing, batch, digest });
+            } else if view == self.view {
+                let message = ConsensusMessage::new(leader, seq, view, ConsensusMessageKind::PrePrepare(batch));
+                self.ready.push_back(message);
+            }
+        }
+        self.execute_ready();
+        eprintln!("Replica r{} recovered at seq {} in view {}", self.node.id(), self.seq, self.view);
+        Ok(())
+    }
+
+    fn new_consensus_msg(&self, seq: i32, kind: ConsensusMessageKind) -> SystemMessage {
+        SystemMessage::Consensus(ConsensusMessage::new(self.node.id(), seq, self.view, kind))
+    }
+}
+
+fn check_config(cfg: &Config) -> io::Result<()> {
+    if cfg.addrs.len() < (3*cfg.f as usize + 1) {
+        let e = io::Error::other("invalid no. of replicas");
+        return Err(e);
+    }
+    if cfg.id as usize >= cfg.addrs.len() {
+        let e = io::Error::other("invalid node id");
+        return Err(e);
+    }
+    if cfg.window < 1 {
+        let e = io::Error::other("the window must fit at least one instance");
+        return Err(e);
+    }
+    if let Some(fault) = cfg.fault {
+        eprintln!("Replica r{} is faulty: {:?}", cfg.id, fault);
+    }
+    Ok(())
+}
+
+// the replica stops on ctrl-c, or when it is told to terminate
+async fn shutdown() -> io::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        signal = tokio::signal::ctrl_c() => signal,
+        _ = terminate.recv() => Ok(()),
+    }
+}
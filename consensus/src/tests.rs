@@ -0,0 +1,744 @@
+use std::time::Duration;
+
+use crate::app::{self, AppKind, Counter, KeyValue, KvReply, KvRequest};
+use crate::auth::{self, AuthConfig, AuthKind};
+use crate::cert::{Certificates, Rejected};
+use crate::config::ClusterArgs;
+use crate::fault::{Fault, FaultSpec};
+use crate::log::{Checkpoint, Decision, Digest, Log, Snapshot};
+use crate::message::{ConsensusMessage, ConsensusMessageKind, ErrorKind, Message, RequestMessage, Signed, SystemMessage};
+use crate::metrics::{Metrics, MetricsConfig};
+use crate::node;
+use crate::peer::Peer;
+use crate::sim::{SimConfig, Simulator};
+use crate::view::{self, Prepared};
+use crate::wal::{Fsync, Wal, WalConfig, WalEntry};
+
+// seeds per simulation test; soak runs set `SIM_SEEDS` to more
+const SEEDS: u64 = 100;
+
+fn seeds() -> u64 {
+    std::env::var("SIM_SEEDS")
+        .ok()
+        .and_then(|seeds| seeds.parse().ok())
+        .unwrap_or(SEEDS)
+}
+
+fn run(cfg: SimConfig) -> crate::sim::Report {
+    let seed = cfg.seed;
+    match Simulator::new(cfg).run() {
+        Ok(report) => report,
+        Err(violation) => panic!("seed {}: {:?}", seed, violation),
+    }
+}
+
+#[test]
+fn test_cert_duplicate() {
+    let mut certs = Certificates::default();
+    certs.pre_prepare(0, 0, [1; 32]).unwrap();
+    assert!(matches!(certs.pre_prepare(0, 0, [1; 32]), Err(Rejected::Duplicate)));
+    certs.prepare(0, 0, [1; 32], Signed::default()).unwrap();
+    certs.prepare(0, 1, [1; 32], Signed::default()).unwrap();
+    assert!(matches!(certs.prepare(0, 1, [1; 32], Signed::default()), Err(Rejected::Duplicate)));
+    // a second vote does not count, even for another value
+    assert!(matches!(certs.prepare(0, 1, [2; 32], Signed::default()), Err(Rejected::Duplicate)));
+    assert_eq!(certs.prepares(0), 2);
+    assert_eq!(certs.prepare_proof(0).len(), 2);
+    certs.commit(0, 1, [1; 32]).unwrap();
+    assert!(matches!(certs.commit(0, 1, [1; 32]), Err(Rejected::Duplicate)));
+    assert_eq!(certs.commits(0), 1);
+}
+
+#[test]
+fn test_cert_mismatch() {
+    let mut certs = Certificates::default();
+    certs.pre_prepare(0, 0, [1; 32]).unwrap();
+    certs.prepare(0, 0, [1; 32], Signed::default()).unwrap();
+    assert!(matches!(certs.prepare(0, 1, [2; 32], Signed::default()), Err(Rejected::Mismatch)));
+    assert!(matches!(certs.commit(0, 1, [2; 32]), Err(Rejected::Mismatch)));
+    assert_eq!(certs.prepares(0), 1);
+    // only the votes for the accepted value prove it
+    assert_eq!(certs.prepare_proof(0).len(), 1);
+    assert_eq!(certs.commits(0), 0);
+    for from in [2, 3] {
+        certs.commit(0, from, [1; 32]).unwrap();
+    }
+    assert_eq!(certs.decided(0, 3), None);
+    certs.commit(0, 0, [1; 32]).unwrap();
+    assert_eq!(certs.decided(0, 3), Some([1; 32]));
+}
+
+#[test]
+fn test_cert_equivocation() {
+    let mut certs = Certificates::default();
+    certs.pre_prepare(3, 0, [1; 32]).unwrap();
+    match certs.pre_prepare(3, 0, [2; 32]) {
+        Err(Rejected::Equivocation(e)) => assert_eq!((e.seq, e.from), (3, 0)),
+        r => panic!("{:?}", r),
+    }
+    assert_eq!(certs.evidence().len(), 1);
+    let e = certs.evidence()[0];
+    assert_eq!((e.seq, e.from, e.first, e.second), (3, 0, [1; 32], [2; 32]));
+    // the first proposal still stands
+    certs.prepare(3, 1, [1; 32], Signed::default()).unwrap();
+    assert!(matches!(certs.prepare(3, 2, [2; 32], Signed::default()), Err(Rejected::Mismatch)));
+    assert_eq!(certs.prepares(3), 1);
+}
+
+// a log with a checkpoint every 4 instances, which executed 0 to 5
+fn log_up_to_5() -> (Log, Digest) {
+    let mut log = Log::new(4);
+    let mut checkpoint = None;
+    for seq in 0..6 {
+        let digest = log.decide(Decision { seq, digest: [seq as u8; 32], batch: Vec::new() });
+        assert_eq!(digest.is_some(), seq == 3, "seq {}", seq);
+        checkpoint = checkpoint.or(digest);
+    }
+    let checkpoint = checkpoint.unwrap();
+    log.checkpointed(3, checkpoint);
+    (log, checkpoint)
+}
+
+#[test]
+fn test_log_checkpoint_stable() {
+    let (mut log, digest) = log_up_to_5();
+    assert_eq!((log.low_watermark(), log.high_watermark()), (0, 8));
+    assert!(matches!(log.checkpoint(3, 0, digest, 3, Signed::default()), Checkpoint::Pending));
+    assert!(matches!(log.checkpoint(3, 1, digest, 3, Signed::default()), Checkpoint::Pending));
+    // a second vote does not count
+    assert!(matches!(log.checkpoint(3, 1, digest, 3, Signed::default()), Checkpoint::Pending));
+    assert!(log.proven().is_none());
+    assert!(matches!(log.checkpoint(3, 2, digest, 3, Signed::default()), Checkpoint::Stable(3)));
+    // the log is truncated at the checkpoint, and the watermarks move
+    assert_eq!((log.low_watermark(), log.high_watermark()), (4, 12));
+    let seqs: Vec<i32> = log.decisions().map(|d| d.seq).collect();
+    assert_eq!(seqs, [4, 5]);
+    let (seq, proven, votes) = log.proven().unwrap();
+    assert_eq!((*seq, *proven, votes.len()), (3, digest, 3));
+}
+
+#[test]
+fn test_log_checkpoint_diverged() {
+    let (mut log, digest) = log_up_to_5();
+    for from in 0..2 {
+        log.checkpoint(3, from, [9; 32], 3, Signed::default());
+    }
+    assert!(matches!(log.checkpoint(3, 2, [9; 32], 3, Signed::default()), Checkpoint::Diverged(3)));
+    // nothing is truncated, and our own digest may still become stable
+    assert_eq!(log.low_watermark(), 0);
+    assert_eq!(log.decisions().count(), 6);
+    assert!(log.proven().is_none());
+    for from in 0..3 {
+        log.checkpoint(3, from, digest, 3, Signed::default());
+    }
+    assert_eq!(log.low_watermark(), 4);
+}
+
+#[test]
+fn test_log_checkpoint_watermarks() {
+    let (mut log, digest) = log_up_to_5();
+    // votes at or above the high watermark are dropped
+    for from in 0..3 {
+        assert!(matches!(log.checkpoint(11, from, [1; 32], 3, Signed::default()), Checkpoint::Pending));
+    }
+    for from in 0..3 {
+        log.checkpoint(3, from, digest, 3, Signed::default());
+    }
+    // and so are the ones below the low watermark, once it moved
+    assert!(matches!(log.checkpoint(3, 3, digest, 3, Signed::default()), Checkpoint::Pending));
+    assert!(matches!(log.vouched(3, digest), Checkpoint::Pending));
+    // the votes dropped before don't count after the move
+    assert!(matches!(log.checkpoint(11, 3, [1; 32], 3, Signed::default()), Checkpoint::Pending));
+}
+
+#[test]
+fn test_log_checkpoint_vouched() {
+    // a state transfer stabilises our own checkpoint without votes
+    let (mut log, digest) = log_up_to_5();
+    assert!(matches!(log.vouched(3, [9; 32]), Checkpoint::Diverged(3)));
+    assert!(matches!(log.vouched(3, digest), Checkpoint::Stable(3)));
+    assert_eq!(log.low_watermark(), 4);
+    assert_eq!(log.decisions().count(), 2);
+    // which can't be proven to the others
+    assert!(log.proven().is_none());
+}
+
+// the authenticators of four replicas, each pair sharing a key
+fn pair_auths() -> Vec<Box<dyn auth::Authenticator>> {
+    (0..4)
+        .map(|id: u32| {
+            let keys = (0..4).map(|other: u32| vec![id.min(other) as u8, id.max(other) as u8]).collect();
+            auth::hmac_vector(id, keys)
+        })
+        .collect()
+}
+
+#[test]
+fn test_view_change_forged_proof() {
+    let auths = pair_auths();
+    let digest = crate::log::batch_digest(&[]);
+    let vote = |from: u32, by: u32| {
+        let m = ConsensusMessage::new(from, 5, 0, ConsensusMessageKind::Prepare(digest));
+        Signed::new(&SystemMessage::Consensus(m), &*auths[by as usize])
+    };
+    let view_change = |proof| {
+        let prepared = Prepared { seq: 5, view: 0, batch: Vec::new(), proof };
+        let kind = ConsensusMessageKind::ViewChange(Vec::new(), vec![prepared]);
+        ConsensusMessage::new(1, 5, 1, kind)
+    };
+    let honest = view_change(vec![vote(0, 0), vote(1, 1), vote(2, 2)]);
+    assert!(view::valid_view_change(&honest, 1, 4, 3, &*auths[3]));
+    // replica 1 can't vote in the name of replica 2
+    let forged = view_change(vec![vote(0, 0), vote(1, 1), vote(2, 1)]);
+    assert!(!view::valid_view_change(&forged, 1, 4, 3, &*auths[3]));
+    // nor count its own vote twice
+    let repeated = view_change(vec![vote(0, 0), vote(1, 1), vote(1, 1)]);
+    assert!(!view::valid_view_change(&repeated, 1, 4, 3, &*auths[3]));
+}
+
+#[test]
+fn test_sim_deterministic() {
+    let cfg = SimConfig {
+        seed: 42,
+        reorder: 0.2,
+        drop: 0.05,
+        ..SimConfig::default()
+    };
+    let a = run(cfg.clone());
+    let b = run(cfg);
+    assert_eq!(a.completed, b.completed);
+    assert_eq!(a.decided, b.decided);
+    assert_eq!(a.elapsed, b.elapsed);
+}
+
+#[test]
+fn test_sim_all_requests_complete() {
+    for seed in 0..seeds() {
+        let cfg = SimConfig {
+            seed,
+            reorder: 0.3,
+            ..SimConfig::default()
+        };
+        let total = cfg.clients * cfg.requests_per_client;
+        let quorum = (cfg.n - cfg.f) as usize;
+        let report = run(cfg);
+        assert_eq!(report.completed, total, "seed {}", seed);
+        // a replica that suspected the leader on its own waits for
+        // a view change, only catching up a watermark at a time
+        let max = report.decided.iter().max().copied().unwrap_or(0);
+        let up_to_date = report.decided.iter().filter(|&&d| d == max).count();
+        assert!(up_to_date >= quorum, "seed {}: {:?}", seed, report.decided);
+    }
+}
+
+#[test]
+fn test_sim_leader_crash() {
+    for seed in 0..seeds() {
+        let cfg = SimConfig {
+            seed,
+            reorder: 0.1,
+            crash: Some((0, Duration::from_millis(5 + seed % 20))),
+            ..SimConfig::default()
+        };
+        let total = cfg.clients * cfg.requests_per_client;
+        let report = run(cfg);
+        assert_eq!(report.completed, total, "seed {}", seed);
+    }
+}
+
+#[test]
+fn test_sim_state_transfer() {
+    for seed in 0..seeds() {
+        let cfg = SimConfig {
+            seed,
+            reorder: 0.1,
+            requests_per_client: 40,
+            isolate: Some((3, Duration::from_millis(5), Duration::from_millis(60))),
+            ..SimConfig::default()
+        };
+        let total = cfg.clients * cfg.requests_per_client;
+        let window = 2 * cfg.checkpoint_period;
+        let report = run(cfg);
+        assert_eq!(report.completed, total, "seed {}", seed);
+        // it may still wait on a view change of its own, in which
+        // case it only catches up once it is a watermark behind
+        let max = report.decided.iter().max().copied().unwrap_or(0);
+        assert!(report.decided[3] + window >= max, "seed {}: {:?}", seed, report.decided);
+    }
+}
+
+#[test]
+fn test_sim_agreement_with_drops() {
+    for seed in 0..seeds() {
+        run(SimConfig {
+            seed,
+            reorder: 0.2,
+            drop: 0.05,
+            max_time: Duration::from_secs(10),
+            ..SimConfig::default()
+        });
+    }
+}
+
+#[test]
+fn test_sim_pipelining() {
+    // with more clients than fit in a batch, the leader has
+    // proposals ready long before the previous one is decided
+    let elapsed = |window| {
+        (0..seeds() / 20)
+            .map(|seed| {
+                let cfg = SimConfig {
+                    seed,
+                    clients: 8,
+                    batch_size: 2,
+                    window,
+                    ..SimConfig::default()
+                };
+                let total = cfg.clients * cfg.requests_per_client;
+                let report = run(cfg);
+                assert_eq!(report.completed, total, "seed {}", seed);
+                report.elapsed
+            })
+            .sum::<Duration>()
+    };
+    let (sequential, pipelined) = (elapsed(1), elapsed(4));
+    assert!(pipelined * 4 < sequential * 3, "{:?} vs {:?}", pipelined, sequential);
+}
+
+#[test]
+fn test_sim_byzantine() {
+    // the leader is replaced unless it only slows things down,
+    // and the honest replicas agree on every instance either way
+    let faults = [
+        (0, Fault::Silent),
+        (0, Fault::Equivocate),
+        (2, Fault::WrongSeq),
+        (0, Fault::Slow(Duration::from_millis(20))),
+        (3, Fault::Slow(Duration::from_millis(200))),
+    ];
+    for (id, fault) in faults {
+        for seed in 0..seeds() / 10 {
+            let cfg = SimConfig {
+                seed,
+                reorder: 0.1,
+                faults: vec![(id, fault)],
+                ..SimConfig::default()
+            };
+            let total = cfg.clients * cfg.requests_per_client;
+            let report = run(cfg);
+            assert_eq!(report.completed, total, "{:?} seed {}", fault, seed);
+        }
+    }
+}
+
+#[test]
+fn test_sim_lying_view_change() {
+    // the leader crashes, and a replica that lies about what it
+    // prepared either votes the next one in, or is the next one
+    for liar in [1, 2] {
+        for seed in 0..seeds() / 10 {
+            let cfg = SimConfig {
+                seed,
+                n: 7,
+                f: 2,
+                reorder: 0.1,
+                crash: Some((0, Duration::from_millis(20))),
+                faults: vec![(liar, Fault::Lie)],
+                ..SimConfig::default()
+            };
+            let total = cfg.clients * cfg.requests_per_client;
+            let report = run(cfg);
+            assert_eq!(report.completed, total, "liar r{} seed {}", liar, seed);
+        }
+    }
+}
+
+#[test]
+fn test_sim_forged_view_change() {
+    // as above, but the lies carry a vote from every replica,
+    // which only their authentication gives away
+    for forger in [1, 2] {
+        for seed in 0..seeds() / 10 {
+            let cfg = SimConfig {
+                seed,
+                n: 7,
+                f: 2,
+                reorder: 0.1,
+                crash: Some((0, Duration::from_millis(20))),
+                faults: vec![(forger, Fault::Forge)],
+                ..SimConfig::default()
+            };
+            let total = cfg.clients * cfg.requests_per_client;
+            let report = run(cfg);
+            assert_eq!(report.completed, total, "forger r{} seed {}", forger, seed);
+        }
+    }
+}
+
+#[test]
+fn test_fault_spec() {
+    let parse = |s: &str| s.parse::<FaultSpec>().map(|spec| (spec.id, spec.fault));
+    assert_eq!(parse("0=silent"), Ok((0, Fault::Silent)));
+    assert_eq!(parse("3=wrong-seq"), Ok((3, Fault::WrongSeq)));
+    assert_eq!(parse("2=lie"), Ok((2, Fault::Lie)));
+    assert_eq!(parse("1=forge"), Ok((1, Fault::Forge)));
+    assert_eq!(parse("1=slow"), Ok((1, Fault::Slow(crate::fault::SLOW_DELAY))));
+    assert_eq!(parse("2=slow:250"), Ok((2, Fault::Slow(Duration::from_millis(250)))));
+    assert!(parse("2=slow:x").is_err());
+    assert!(parse("equivocate").is_err());
+    assert!(parse("1=lazy").is_err());
+}
+
+#[test]
+fn test_metrics_csv() {
+    let start = std::time::Instant::now();
+    let at = |ms| start + Duration::from_millis(ms);
+    let request = |id| RequestMessage { client: 0, id, op: Vec::new() };
+    let mut metrics = Metrics::new(0, MetricsConfig::default(), start);
+    metrics.received(&request(0), at(1));
+    metrics.received(&request(1), at(2));
+    metrics.proposed(0, &[request(0), request(1)], at(4));
+    // decided by a commit quorum before a prepare quorum
+    metrics.proposed(1, &[], at(5));
+    metrics.committed(1, at(9));
+    metrics.prepared(0, at(7));
+    metrics.committed(0, at(8));
+    metrics.executed(0, &[request(0), request(1)], at(10));
+    metrics.executed(1, &[], at(11));
+    // fetched from the others
+    metrics.executed(2, &[request(2)], at(12));
+
+    let mut csv = Vec::new();
+    metrics.write_csv(&mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(lines, [
+        "seq,batch,received,proposed,prepared,committed,executed,pre-prepare,prepare,commit,consensus,execution",
+        "0,2,1000,4000,7000,8000,10000,3000,3000,1000,4000,2000",
+        "1,0,,5000,9000,9000,11000,,4000,0,4000,2000",
+    ]);
+}
+
+// every replica accepts a tag from every other one, and
+// rejects it once the payload or the claimed sender change
+fn check_auth(cfg: &AuthConfig) {
+    let n = 4;
+    let auths: Vec<_> = (0..n)
+        .map(|id| auth::new(cfg, id, n).unwrap())
+        .collect();
+    for from in 0..n {
+        let tag = auths[from as usize].tag(b"payload");
+        assert_eq!(tag.len(), auths[from as usize].tag_len());
+        for to in 0..n {
+            let a = &auths[to as usize];
+            assert!(a.verify(from, b"payload", &tag));
+            assert!(!a.verify(from, b"payloaf", &tag));
+            assert!(!a.verify((from + 1) % n, b"payload", &tag));
+        }
+    }
+}
+
+// a fresh directory of keys for four replicas
+fn key_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("consensus-{}-{}", name, std::process::id()));
+    auth::generate_keys(&dir, 4).unwrap();
+    dir
+}
+
+#[test]
+fn test_auth_hmac() {
+    let key_dir = key_dir("hmac");
+    let cfg = AuthConfig {
+        kind: AuthKind::Hmac,
+        key_dir: key_dir.clone(),
+    };
+    check_auth(&cfg);
+    // every pair has a key of its own
+    let a = std::fs::read(key_dir.join("r0-r1.key")).unwrap();
+    let b = std::fs::read(key_dir.join("r0-r2.key")).unwrap();
+    assert_ne!(a, b);
+    // and a replica can't start without its keys
+    std::fs::remove_file(key_dir.join("r1-r2.key")).unwrap();
+    assert!(auth::new(&cfg, 0, 4).is_ok());
+    assert!(auth::new(&cfg, 2, 4).is_err());
+    std::fs::remove_dir_all(key_dir).unwrap();
+}
+
+#[test]
+fn test_auth_ed25519() {
+    let key_dir = key_dir("ed25519");
+    check_auth(&AuthConfig {
+        kind: AuthKind::Ed25519,
+        key_dir: key_dir.clone(),
+    });
+    std::fs::remove_dir_all(key_dir).unwrap();
+}
+
+#[test]
+fn test_app_counter() {
+    let mut counter = app::new(AppKind::Counter);
+    assert_eq!(Counter::reply(&counter.execute(&Counter::op(5))), Some(5));
+    assert_eq!(Counter::reply(&counter.execute(&Counter::op(-2))), Some(3));
+    // garbage is a no-op, with the same reply everywhere
+    assert_eq!(Counter::reply(&counter.execute(b"?")), Some(3));
+
+    let mut restored = app::new(AppKind::Counter);
+    restored.restore(&counter.snapshot()).unwrap();
+    assert_eq!(Counter::reply(&restored.execute(&Counter::op(1))), Some(4));
+}
+
+#[test]
+fn test_app_kv() {
+    let mut kv = app::new(AppKind::Kv);
+    let execute = |kv: &mut Box<dyn app::Application>, request| {
+        KeyValue::reply(&kv.execute(&KeyValue::op(&request))).unwrap()
+    };
+    let write = |k: &str, v: &str| KvRequest::Write(k.into(), v.into());
+    assert_eq!(execute(&mut kv, write("a", "1")), KvReply::None);
+    assert_eq!(execute(&mut kv, write("a", "2")), KvReply::Value("1".into()));
+    assert_eq!(execute(&mut kv, write("b", "3")), KvReply::None);
+    assert_eq!(execute(&mut kv, KvRequest::Delete("b".into())), KvReply::Value("3".into()));
+    assert_eq!(KeyValue::reply(&kv.execute(b"?")), Some(KvReply::Invalid));
+
+    let mut restored = app::new(AppKind::Kv);
+    restored.restore(&kv.snapshot()).unwrap();
+    assert_eq!(restored.snapshot(), kv.snapshot());
+    assert_eq!(execute(&mut restored, KvRequest::Read("a".into())), KvReply::Value("2".into()));
+    assert_eq!(execute(&mut restored, KvRequest::Read("b".into())), KvReply::None);
+    assert!(restored.restore(b"?").is_err());
+}
+
+#[test]
+fn test_config_load() {
+    #[derive(clap::Parser)]
+    struct Args {
+        #[command(flatten)]
+        cluster: ClusterArgs,
+    }
+    let path = std::env::temp_dir().join(format!("consensus-config-{}.toml", std::process::id()));
+    let load = |contents: &str, flags: &[&str]| {
+        std::fs::write(&path, contents).unwrap();
+        let args = ["replica", "--config", path.to_str().unwrap()];
+        <Args as clap::Parser>::parse_from(args.iter().chain(flags)).cluster.load()
+    };
+
+    let cluster = load(
+        r#"
+        f = 1
+        replicas = ["10.0.0.1:52000", "10.0.0.2:52000", "10.0.0.3:52000", "10.0.0.4:52000"]
+        auth = "hmac"
+        app = "kv"
+        "#,
+        &[],
+    )
+    .unwrap();
+    assert_eq!(cluster.f, 1);
+    assert_eq!(cluster.clients[3], "10.0.0.4:53000".parse().unwrap());
+    assert_eq!(cluster.auth.kind, AuthKind::Hmac);
+    assert_eq!(cluster.app, AppKind::Kv);
+    let (listen, client_listen) = cluster.listen_addrs(2).unwrap();
+    assert_eq!((listen.port(), client_listen.port()), (52000, 53000));
+    assert!(cluster.listen_addrs(4).is_none());
+
+    // flags take precedence over the file
+    let cluster = load("localhost = true\nbase_port = 40000\n", &["--f", "2", "--base-port", "41000"]).unwrap();
+    assert_eq!(cluster.replicas.len(), 7);
+    assert_eq!(cluster.replicas[6].port(), 41006);
+    assert_eq!(cluster.clients[0].port(), 42000);
+
+    // ports past 65535
+    assert!(load("localhost = true\nbase_port = 65000\n", &[]).is_err());
+    assert!(load("localhost = true\nbase_port = 64000\nn = 1600\n", &[]).is_err());
+    assert!(load(r#"replicas = ["10.0.0.1:65000"]"#, &[]).is_err());
+
+    // malformed files, and files that contradict themselves
+    assert!(load("f = ", &[]).is_err());
+    assert!(load("f = -1", &[]).is_err());
+    assert!(load("replica = []", &[]).is_err());
+    assert!(load(r#"replicas = ["10.0.0.1"]"#, &[]).is_err());
+    assert!(load("", &[]).is_err());
+    assert!(load(r#"n = 2
+        replicas = ["10.0.0.1:52000"]"#, &[]).is_err());
+    assert!(load(r#"replicas = ["10.0.0.1:52000"]
+        clients = ["10.0.0.1:53000", "10.0.0.2:53000"]"#, &[]).is_err());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_wal_recovery() {
+    let path = std::env::temp_dir().join(format!("consensus-wal-{}", std::process::id()));
+    let cfg = WalConfig { path: path.clone(), fsync: Fsync::Always };
+    let commit = |seq| WalEntry::Commit { seq, view: 0, digest: [seq as u8; 32] };
+    let seqs = |entries: &[WalEntry]| -> Vec<i32> {
+        entries
+            .iter()
+            .map(|e| match e {
+                WalEntry::Commit { seq, .. } => *seq,
+                WalEntry::Checkpoint(s) => -s.seq,
+                _ => panic!("{:?}", e),
+            })
+            .collect()
+    };
+
+    let (mut wal, entries) = Wal::open(cfg.clone()).unwrap();
+    assert!(entries.is_empty());
+    for seq in 0..4 {
+        wal.append(&commit(seq)).unwrap();
+    }
+    drop(wal);
+
+    // a crash in the middle of a write
+    let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+    std::io::Write::write_all(&mut file, &[0, 0, 1, 0, 42]).unwrap();
+    drop(file);
+
+    let (mut wal, entries) = Wal::open(cfg.clone()).unwrap();
+    assert_eq!(seqs(&entries), [0, 1, 2, 3]);
+    wal.append(&commit(4)).unwrap();
+    let snapshot = Snapshot { seq: 2, log: [0; 32], state: Vec::new(), replies: Vec::new() };
+    wal.compact(&[WalEntry::Checkpoint(snapshot)], 3).unwrap();
+    wal.append(&commit(5)).unwrap();
+    drop(wal);
+
+    let (_, entries) = Wal::open(cfg).unwrap();
+    assert_eq!(seqs(&entries), [-2, 3, 4, 5]);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn test_peer_writer() {
+    use tokio::io::AsyncReadExt;
+
+    // the peer comes up only after we queued frames for it
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+    let (tx, _rx) = node::new_message_channel(8);
+    let peer = Peer::connect(7, 0, addr, tx);
+    // big enough for writes to be cut short
+    let frame = |i: usize| -> Vec<u8> { vec![i as u8; 1 + i * 997 % 20000] };
+    for i in 0..300 {
+        peer.send(frame(i).into());
+    }
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    let (mut conn, _) = listener.accept().await.unwrap();
+    assert_eq!(conn.read_u32().await.unwrap(), 7);
+    for i in 0..300 {
+        let mut buf = frame(i);
+        conn.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, frame(i), "frame {}", i);
+    }
+}
+
+#[tokio::test]
+async fn test_peer_reconnect() {
+    use tokio::io::AsyncReadExt;
+    use tokio::time::Instant;
+
+    // drops the connection, and returns when the writer noticed
+    async fn lose(peer: &Peer, rx: &mut node::MessageChannelRx) -> Instant {
+        loop {
+            peer.send(vec![0; 1024].into());
+            let lost = tokio::time::timeout(Duration::from_millis(10), rx.recv());
+            if let Ok(Some(Message::Error(ErrorKind::DisconnectedTx(0)))) = lost.await {
+                return Instant::now();
+            }
+        }
+    }
+    async fn accept(addr: std::net::SocketAddr) -> (tokio::net::TcpListener, tokio::net::TcpStream) {
+        let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+        let (mut conn, _) = listener.accept().await.unwrap();
+        assert_eq!(conn.read_u32().await.unwrap(), 7);
+        (listener, conn)
+    }
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+    let (tx, mut rx) = node::new_message_channel(8);
+    let peer = Peer::connect(7, 0, addr, tx);
+    let (listener, conn) = accept(addr).await;
+
+    // retries at 0, 100, 300, 700, 1500 and 3100ms, so one that
+    // kept retrying every 100ms would be back right after 2s
+    drop((listener, conn));
+    let lost = lose(&peer, &mut rx).await;
+    tokio::time::sleep_until(lost + Duration::from_secs(2)).await;
+    let (listener, conn) = accept(addr).await;
+    let elapsed = lost.elapsed();
+    assert!(elapsed > Duration::from_millis(2500), "{:?}", elapsed);
+
+    // back to retrying every 100ms after reconnecting
+    drop((listener, conn));
+    let lost = lose(&peer, &mut rx).await;
+    tokio::time::sleep_until(lost + Duration::from_millis(200)).await;
+    accept(addr).await;
+    let elapsed = lost.elapsed();
+    assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+}
+
+#[tokio::test]
+async fn test_frame_limit() {
+    let mut ok: &[u8] = &[0, 0, 0, 3, 1, 2, 3];
+    assert_eq!(&node::read_raw_frame(&mut ok).await.unwrap()[..], [1, 2, 3]);
+    // rejected from its length alone, before we wait for the bytes
+    let mut huge: &[u8] = &[0xff, 0xff, 0xff, 0xff];
+    let e = node::read_raw_frame(&mut huge).await.unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn test_duplicate_client() {
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use crate::message::{ErrorKind, Message};
+
+    let free = || std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let (listen, client_listen) = (free(), free());
+    let auth = Arc::from(auth::new(&AuthConfig::default(), 0, 1).unwrap());
+    let mut node = node::Node::bootstrap(0, vec![listen], listen, client_listen, auth, None).await.unwrap();
+    let connect = || async {
+        let mut conn = TcpStream::connect(client_listen).await.unwrap();
+        conn.write_u32(5).await.unwrap();
+        conn
+    };
+    let first = connect().await;
+    assert!(matches!(node.receive().await.unwrap(), Message::ConnectedClient(5, _)));
+    // a second connection can't take over the id
+    let mut second = connect().await;
+    assert_eq!(second.read(&mut [0]).await.unwrap(), 0);
+    // until the first one is gone
+    drop(first);
+    let m = node.receive().await.unwrap();
+    assert!(matches!(m, Message::Error(ErrorKind::DisconnectedClient(5))), "{:?}", m);
+    node.client_disconnected(5);
+    let _third = connect().await;
+    assert!(matches!(node.receive().await.unwrap(), Message::ConnectedClient(5, _)));
+}
+
+#[tokio::test]
+async fn test_client_retransmit() {
+    use tokio::io::AsyncReadExt;
+    use crate::client::Client;
+    use crate::message::ReplyMessage;
+
+    // a replica that only replies to the request once it gets it again
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let replica = tokio::spawn(async move {
+        let (mut conn, _) = listener.accept().await.unwrap();
+        assert_eq!(conn.read_u32().await.unwrap(), 7);
+        let first: RequestMessage = node::read_frame(&mut conn).await.unwrap();
+        let again: RequestMessage = node::read_frame(&mut conn).await.unwrap();
+        assert_eq!((first.id, again.id, again.op), (0, 0, b"op".to_vec()));
+        let reply = ReplyMessage { client: 7, id: 0, seq: 0, result: b"ok".to_vec() };
+        node::write_frame(&mut conn, &reply).await.unwrap();
+        conn
+    });
+    let mut client = Client::connect(7, 0, &[addr]).await.unwrap();
+    client.retransmit_after(Duration::from_millis(20));
+    let reply = client.invoke(b"op".to_vec()).await.unwrap();
+    assert_eq!(reply.result, b"ok");
+    replica.await.unwrap();
+}
@@ -0,0 +1,115 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
+
+use crate::log::{batch_digest, Decision, Digest, Snapshot};
+
+/// The state of a replica, as offered in a `State` message.
+#[derive(Debug, Clone)]
+pub struct Offer {
+    pub snapshot: Snapshot,
+    /// Values decided after `snapshot`, in order.
+    pub decisions: Vec<Decision>,
+    /// The sender's view, and the sequence number it started on.
+    pub view: (u32, i32),
+}
+
+/// What at least one correct replica vouches for, among the offers.
+#[derive(Debug, Clone)]
+pub struct Verified {
+    pub snapshot: Snapshot,
+    pub decisions: Vec<Decision>,
+    pub view: Option<(u32, i32)>,
+}
+
+/// State of the state transfer sub-protocol, used by replicas
+/// that fell too far behind to catch up by taking part in the
+/// consensus instances they missed.
+#[derive(Debug, Default)]
+pub struct StateTransfer {
+    /// When we last asked the others for their state.
+    pub requested: Option<Instant>,
+    offers: HashMap<u32, (Digest, Offer)>,
+    // the first instance each replica said it hadn't executed
+    progress: HashMap<u32, i32>,
+}
+
+impl StateTransfer {
+    /// Note that `from` executed every instance below `seq`.
+    pub fn progress(&mut self, from: u32, seq: i32) {
+        let progress = self.progress.entry(from).or_insert(seq);
+        *progress = seq.max(*progress);
+    }
+
+    /// Whether a correct replica is known to have executed `seq`.
+    pub fn behind(&self, seq: i32, f: u32) -> bool {
+        let ahead = self.progress
+            .values()
+            .filter(|&&progress| progress > seq)
+            .count();
+        ahead as u32 > f
+    }
+
+    /// Store the offer of `from`, replacing its previous one.
+    pub fn receive(&mut self, from: u32, mut offer: Offer) {
+        // a decision that doesn't match its batch, and
+        // everything after it, can't be vouched for
+        let valid = offer.decisions
+            .iter()
+            .take_while(|d| d.digest == batch_digest(&d.batch))
+            .count();
+        offer.decisions.truncate(valid);
+        let digest = offer.snapshot.digest();
+        self.offers.insert(from, (digest, offer));
+    }
+
+    /// The latest checkpoint `f + 1` replicas offered, and the
+    /// values `f + 1` replicas decided right after it.
+    pub fn select(&self, f: u32) -> Option<Verified> {
+        let enough = |votes: usize| votes as u32 > f;
+
+        let mut checkpoints: BTreeMap<(i32, Digest), usize> = BTreeMap::new();
+        for (digest, offer) in self.offers.values() {
+            *checkpoints.entry((offer.snapshot.seq, *digest)).or_default() += 1;
+        }
+        let (seq, digest) = checkpoints
+            .into_iter()
+            .rev()
+            .find(|&(_, votes)| enough(votes))
+            .map(|(checkpoint, _)| checkpoint)?;
+        let snapshot = self.offers
+            .values()
+            .find(|(d, offer)| *d == digest && offer.snapshot.seq == seq)
+            .map(|(_, offer)| offer.snapshot.clone())?;
+
+        let mut decisions = Vec::new();
+        for next in seq + 1.. {
+            let mut votes: HashMap<Digest, (usize, &Decision)> = HashMap::new();
+            for (_, offer) in self.offers.values() {
+                if let Some(d) = offer.decisions.iter().find(|d| d.seq == next) {
+                    votes.entry(d.digest).or_insert((0, d)).0 += 1;
+                }
+            }
+            match votes.into_values().find(|&(votes, _)| enough(votes)) {
+                Some((_, d)) => decisions.push(d.clone()),
+                None => break,
+            }
+        }
+
+        let mut views: HashMap<(u32, i32), usize> = HashMap::new();
+        for (_, offer) in self.offers.values() {
+            *views.entry(offer.view).or_default() += 1;
+        }
+        let view = views
+            .into_iter()
+            .filter(|&(_, votes)| enough(votes))
+            .map(|(view, _)| view)
+            .max();
+
+        Some(Verified { snapshot, decisions, view })
+    }
+
+    pub fn reset(&mut self) {
+        self.requested = None;
+        self.offers.clear();
+    }
+}
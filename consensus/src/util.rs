@@ -0,0 +1,40 @@
+//! Helpers shared by the library and the binaries.
+
+use std::time::Duration;
+
+/// Deterministic pseudo random numbers.
+#[derive(Debug, Clone)]
+pub struct Prng {
+    seed: u64,
+}
+
+impl Prng {
+    pub fn new(seed: u64) -> Self {
+        // skip the first, poorly distributed outputs
+        let mut prng = Prng { seed };
+        prng.next();
+        prng.next();
+        prng
+    }
+
+    /// A number in `[0, 1)`.
+    pub fn unit(&mut self) -> f64 {
+        self.next().unwrap() as f64 / (1_u64 << 31) as f64
+    }
+
+    pub fn range(&mut self, lo: Duration, hi: Duration) -> Duration {
+        lo + (hi - lo).mul_f64(self.unit())
+    }
+}
+
+impl Iterator for Prng {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        const MAGIC: u64 = 6364136223846793005;
+        self.seed = MAGIC
+            .wrapping_mul(self.seed)
+            .wrapping_add(1442695040888963407);
+        Some((self.seed >> 33) as u32)
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
+
+use serde::{Serialize, Deserialize};
+
+use crate::auth::Authenticator;
+use crate::log::batch_digest;
+use crate::message::{ConsensusMessage, ConsensusMessageKind, RequestMessage, Signed};
+
+/// A batch that gathered a prepare quorum at `seq`, during `view`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Prepared {
+    pub seq: i32,
+    pub view: u32,
+    pub batch: Vec<RequestMessage>,
+    /// The matching `Prepare` messages of the quorum, as their
+    /// senders authenticated them.
+    pub proof: Vec<Signed>,
+}
+
+/// State of the view change sub-protocol.
+#[derive(Debug)]
+pub struct ViewChanges {
+    /// The view we are trying to install, if any.
+    pub pending: Option<u32>,
+    /// The sequence number the current view started on.
+    pub start: i32,
+    /// Messages sent in views we haven't installed yet.
+    pub future: Vec<ConsensusMessage>,
+    /// Id of the only timer that may still go off.
+    pub timer: u64,
+    /// Whether it hasn't gone off yet.
+    pub armed: bool,
+    /// How long to wait for progress before suspecting the leader.
+    pub timeout: Duration,
+    base_timeout: Duration,
+    received: HashMap<u32, BTreeMap<u32, ConsensusMessage>>,
+}
+
+impl ViewChanges {
+    pub fn new(timeout: Duration) -> Self {
+        ViewChanges {
+            pending: None,
+            start: 0,
+            future: Vec::new(),
+            timer: 0,
+            armed: false,
+            timeout,
+            base_timeout: timeout,
+            received: HashMap::new(),
+        }
+    }
+
+    /// Store a `ViewChange` message, and return the number of distinct
+    /// replicas that asked to move to the same view.
+    pub fn receive(&mut self, message: ConsensusMessage) -> u32 {
+        let votes = self.received
+            .entry(message.view)
+            .or_default();
+        votes.entry(message.from).or_insert(message);
+        votes.len() as u32
+    }
+
+    /// Remove and return a quorum of `ViewChange` messages for `view`.
+    pub fn take_proof(&mut self, view: u32, quorum: u32) -> Option<Vec<ConsensusMessage>> {
+        match self.received.get(&view) {
+            Some(votes) if votes.len() as u32 >= quorum => (),
+            _ => return None,
+        }
+        let votes = self.received.remove(&view)?;
+        Some(votes.into_values().take(quorum as usize).collect())
+    }
+
+    /// Forget everything related to views up to and including `view`.
+    pub fn installed(&mut self, view: u32, start: i32) {
+        self.pending = None;
+        self.start = start;
+        self.received.retain(|&v, _| v > view);
+    }
+
+    pub fn reset_timeout(&mut self) {
+        self.timeout = self.base_timeout;
+    }
+}
+
+/// Check that a `ViewChange` message for `view`, from one of the `n`
+/// replicas, backs what it reports: its stable checkpoint, if any,
+/// with `quorum` matching `Checkpoint` messages, and each value it
+/// prepared in an earlier view with `quorum` matching `Prepare` messages,
+/// each of them authenticated by its sender.
+pub fn valid_view_change(m: &ConsensusMessage, view: u32, n: u32, quorum: u32, auth: &dyn Authenticator) -> bool {
+    let (checkpoint, prepared) = match m.kind {
+        ConsensusMessageKind::ViewChange(ref checkpoint, ref prepared) => (checkpoint, prepared),
+        _ => return false,
+    };
+    let valid_checkpoint = checkpoint.is_empty() || proven_checkpoint(checkpoint, n, quorum, auth).is_some();
+    let valid_prepared = prepared
+        .iter()
+        .all(|p| p.view < view && valid_certificate(p, n, quorum, auth));
+    m.view == view && m.from < n && valid_checkpoint && valid_prepared
+}
+
+/// Open the proof of a `NewView` message for `view`, if it is made up of
+/// valid `ViewChange` messages for that view, from `quorum` distinct
+/// replicas, each authenticated by its sender.
+pub fn open_proof(proof: &[Signed], view: u32, n: u32, quorum: u32, auth: &dyn Authenticator) -> Option<Vec<ConsensusMessage>> {
+    let mut senders = HashSet::new();
+    let opened = proof
+        .iter()
+        .map(|signed| signed.open(auth))
+        .collect::<Option<Vec<_>>>()?;
+    let all_valid = opened
+        .iter()
+        .all(|m| valid_view_change(m, view, n, quorum, auth) && senders.insert(m.from));
+    (all_valid && senders.len() as u32 >= quorum).then_some(opened)
+}
+
+/// Compute the sequence number the new view starts on, and the values
+/// the new leader needs to propose again, from a valid proof.
+///
+/// Everything up to the latest stable checkpoint in the proof has been
+/// decided. So has everything below the first instance `f + 1` of the
+/// replicas haven't executed, as at least one of them is correct. Above
+/// that, the value prepared in the highest view must be kept. Instances
+/// in between prepared ones were decided nowhere, and get an empty batch
+/// so that the new view doesn't start with a gap.
+pub fn select(proof: &[ConsensusMessage], f: u32) -> (i32, Vec<Prepared>) {
+    let checkpoint = proof
+        .iter()
+        .filter_map(|m| match m.kind {
+            ConsensusMessageKind::ViewChange(ref checkpoint, _) => checkpoint.first()?.message(),
+            _ => None,
+        })
+        .map(|c| c.seq + 1)
+        .max()
+        .unwrap_or(0);
+    let mut executed: Vec<i32> = proof.iter().map(|m| m.seq).collect();
+    executed.sort_unstable_by(|a, b| b.cmp(a));
+    let start = checkpoint.max(executed.get(f as usize).copied().unwrap_or(0));
+    let mut chosen: BTreeMap<i32, Prepared> = BTreeMap::new();
+    for m in proof {
+        let prepared = match m.kind {
+            ConsensusMessageKind::ViewChange(_, ref prepared) => prepared,
+            _ => continue,
+        };
+        for p in prepared.iter().filter(|p| p.seq >= start) {
+            let current = chosen.entry(p.seq).or_insert_with(|| p.clone());
+            if p.view > current.view {
+                *current = p.clone();
+            }
+        }
+    }
+    let last = chosen.keys().next_back().copied().unwrap_or(start);
+    for seq in start..last {
+        chosen
+            .entry(seq)
+            .or_insert_with(|| Prepared { seq, view: 0, batch: Vec::new(), proof: Vec::new() });
+    }
+    (start, chosen.into_values().collect())
+}
+
+// the checkpoint `quorum` of the `n` replicas vote for in `proof`
+fn proven_checkpoint(proof: &[Signed], n: u32, quorum: u32, auth: &dyn Authenticator) -> Option<i32> {
+    let proof = proof
+        .iter()
+        .map(|signed| signed.open(auth))
+        .collect::<Option<Vec<_>>>()?;
+    let first = proof.first()?;
+    let digest = match first.kind {
+        ConsensusMessageKind::Checkpoint(digest) => digest,
+        _ => return None,
+    };
+    let mut senders = HashSet::new();
+    let all_match = proof
+        .iter()
+        .all(|m| {
+            let matches = matches!(m.kind, ConsensusMessageKind::Checkpoint(d) if d == digest);
+            matches && m.seq == first.seq && m.from < n && senders.insert(m.from)
+        });
+    (all_match && senders.len() as u32 >= quorum).then_some(first.seq)
+}
+
+// whether `quorum` of the `n` replicas voted for the batch of `p`
+fn valid_certificate(p: &Prepared, n: u32, quorum: u32, auth: &dyn Authenticator) -> bool {
+    let digest = batch_digest(&p.batch);
+    let mut senders = HashSet::new();
+    let all_match = p.proof
+        .iter()
+        .all(|signed| {
+            let m = match signed.open(auth) {
+                Some(m) => m,
+                None => return false,
+            };
+            let matches = matches!(m.kind, ConsensusMessageKind::Prepare(d) if d == digest);
+            matches && m.seq == p.seq && m.view == p.view && m.from < n && senders.insert(m.from)
+        });
+    all_match && senders.len() as u32 >= quorum
+}
@@ -0,0 +1,155 @@
+//! Write-ahead log of a replica's consensus decisions.
+//!
+//! Each entry is framed like a network message: a big endian `u32`
+//! length followed by the bincode payload. The file is rewritten
+//! whenever a checkpoint becomes stable, keeping only the state at
+//! the checkpoint and the entries after it.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, Read, Write};
+use std::path::PathBuf;
+
+use serde::{Serialize, Deserialize};
+use tokio::io;
+
+use crate::log::{Digest, Snapshot};
+use crate::message::RequestMessage;
+
+/// When appended entries are flushed to the disk.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Fsync {
+    /// Before acting on each entry, so no decision is lost.
+    #[default]
+    Always,
+    /// Whenever the OS gets to it; a crash of the machine,
+    /// rather than of the process, may lose decisions.
+    Never,
+}
+
+#[derive(Debug, Clone)]
+pub struct WalConfig {
+    pub path: PathBuf,
+    pub fsync: Fsync,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum WalEntry {
+    /// State at the last stable checkpoint; always the first entry.
+    Checkpoint(Snapshot),
+    /// A view was installed, starting on `start`.
+    View { view: u32, start: i32 },
+    /// We accepted `batch` as the proposal for `seq` in `view`.
+    PrePrepare { seq: i32, view: u32, batch: Vec<RequestMessage> },
+    /// A quorum of replicas committed `digest` for `seq`.
+    Commit { seq: i32, view: u32, digest: Digest },
+}
+
+#[derive(Debug)]
+pub struct Wal {
+    cfg: WalConfig,
+    file: File,
+    // the instance entries written since the last checkpoint,
+    // already framed
+    entries: Vec<(i32, Vec<u8>)>,
+}
+
+impl Wal {
+    /// Open the log at `cfg.path`, creating it if needed, and
+    /// return the entries it holds. An entry torn by a crash
+    /// midway through a write is discarded.
+    pub fn open(cfg: WalConfig) -> io::Result<(Self, Vec<WalEntry>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&cfg.path)?;
+        let total = file.metadata()?.len();
+        let (recovered, valid) = read_entries(&mut file, total);
+        if valid < total {
+            file.set_len(valid)?;
+        }
+        let entries = recovered
+            .iter()
+            .filter_map(|e| Some((e.seq()?, frame(e))))
+            .collect();
+        Ok((Wal { cfg, file, entries }, recovered))
+    }
+
+    pub fn append(&mut self, entry: &WalEntry) -> io::Result<()> {
+        let buf = frame(entry);
+        self.file.write_all(&buf)?;
+        if self.cfg.fsync == Fsync::Always {
+            self.file.sync_data()?;
+        }
+        if let Some(seq) = entry.seq() {
+            self.entries.push((seq, buf));
+        }
+        Ok(())
+    }
+
+    /// Replace the log with `head`, followed by the
+    /// instance entries from `from` onwards.
+    pub fn compact(&mut self, head: &[WalEntry], from: i32) -> io::Result<()> {
+        self.entries.retain(|&(seq, _)| seq >= from);
+        let tmp = self.cfg.path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        for entry in head {
+            file.write_all(&frame(entry))?;
+        }
+        for (_, buf) in self.entries.iter() {
+            file.write_all(buf)?;
+        }
+        if self.cfg.fsync == Fsync::Always {
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &self.cfg.path)?;
+        self.file = OpenOptions::new().append(true).open(&self.cfg.path)?;
+        Ok(())
+    }
+}
+
+impl WalEntry {
+    // the instance this entry is about
+    fn seq(&self) -> Option<i32> {
+        match self {
+            WalEntry::PrePrepare { seq, .. } | WalEntry::Commit { seq, .. } => Some(*seq),
+            WalEntry::Checkpoint(_) | WalEntry::View { .. } => None,
+        }
+    }
+}
+
+fn frame(entry: &WalEntry) -> Vec<u8> {
+    let mut buf = vec![0; 4];
+    bincode::serialize_into(&mut buf, entry).unwrap();
+    let len = (buf.len() as u32 - 4).to_be_bytes();
+    buf[..4].copy_from_slice(&len);
+    buf
+}
+
+// the entries in `file`, and the length of the prefix they span
+fn read_entries(file: &mut File, total: u64) -> (Vec<WalEntry>, u64) {
+    let mut reader = BufReader::new(file);
+    let mut entries = Vec::new();
+    let mut valid = 0;
+    let mut len = [0; 4];
+    loop {
+        if reader.read_exact(&mut len).is_err() {
+            break;
+        }
+        let size = u32::from_be_bytes(len) as u64;
+        if valid + 4 + size > total {
+            // the length itself may be garbage
+            break;
+        }
+        let mut buf = vec![0; size as usize];
+        if reader.read_exact(&mut buf).is_err() {
+            break;
+        }
+        match bincode::deserialize(&buf) {
+            Ok(entry) => entries.push(entry),
+            Err(_) => break,
+        }
+        valid += 4 + buf.len() as u64;
+    }
+    (entries, valid)
+}
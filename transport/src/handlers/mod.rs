@@ -1,15 +1,23 @@
-use std::io::{Read, Write};
+use std::fmt;
+use std::io::{self, Read, Write};
 use std::future::Future;
+use std::rc::Rc;
 use std::thread;
+use std::task::Poll;
+use std::time::{Duration, Instant};
 use std::sync::{
     Arc,
     atomic::{AtomicBool, AtomicU64, Ordering},
-    mpsc::{sync_channel, SyncSender},
+    mpsc::{self as std_mpsc, sync_channel, SyncSender},
 };
 
-use super::params;
+use super::params::{Mesh, Params};
 use super::runtime;
-use super::nodes::{Client, Server, AsyncClient, AsyncServer};
+use super::nodes::{
+    Client, Server,
+    AsyncClient, AsyncServer,
+    CompletionClient, CompletionServer,
+};
 use futures_timer::Delay;
 use futures::channel::mpsc;
 use futures::sink::SinkExt;
@@ -19,113 +27,230 @@ use futures::io::{AsyncRead, AsyncWrite, AsyncReadExt, AsyncWriteExt};
 
 pub type Rs<T> = Result<T, Box<dyn std::error::Error>>;
 
-// TODO: fix test1
+/// Echo every frame of one client back to it, over the same
+/// connection, until it hangs up.
 pub fn server_test1_sync<S: Server>(server: S) -> Rs<()> {
-    while let Ok(mut client) = server.accept_client() {
-        read_sync(&mut client)?;
-        write_sync(&mut client)?;
+    let mut c = server.accept_client()?;
+    let mut frame = Vec::new();
+    while read_frame_sync(&mut c, &mut frame)? {
+        c.write_all(&frame)?;
+    }
+    Ok(())
+}
+
+/// Send requests of each payload size to the server over one
+/// connection, with up to `params.depth` of them in flight, and
+/// time how long each takes to come back.
+pub fn client_test1_sync<C>(client: C, params: &Params) -> Rs<()>
+where
+    C: 'static + Client + Send,
+{
+    for &size in params.bufsiz.iter() {
+        let latencies = ping_pong_sync(&client, params, size)?;
+        println!("{} bytes: {}", size, latencies);
     }
     Ok(())
 }
 
-// TODO: fix test1
-pub fn client_test1_sync<C>(mut clients: Vec<C>) -> Rs<f64>
+fn ping_pong_sync<C>(client: &C, params: &Params, size: usize) -> Rs<Latencies>
 where
     C: 'static + Client + Send,
 {
-    testcase(move |_ready, quit| {
-        let mut counter = 0;
-        while !quit.load(Ordering::Relaxed) {
-            for mut c in clients.iter_mut() {
-                write_sync(&mut c).ok()?;
-                read_sync(&mut c).ok()?;
+    let mut r = client.try_clone()?;
+    let mut w = client.try_clone()?;
+    let (credits_tx, credits_rx) = std_mpsc::channel();
+    let (sent_tx, sent_rx) = std_mpsc::channel();
+    for _ in 0..params.depth {
+        credits_tx.send(())?;
+    }
+    let measured = Instant::now() + params.warmup();
+    let end = measured + params.duration();
+    // the replies are read as the requests are written, so
+    // neither side blocks on a full socket buffer
+    let writer = thread::spawn(move || -> io::Result<()> {
+        let frame = new_frame(size);
+        while credits_rx.recv().is_ok() && Instant::now() < end {
+            let sent = Instant::now();
+            w.write_all(&frame)?;
+            if sent_tx.send(sent).is_err() {
+                break;
             }
-            counter += 1;
         }
-        Some(counter)
-    })
-    .map(ops_per_sec)
+        Ok(())
+    });
+    let mut latencies = Vec::new();
+    let mut frame = Vec::new();
+    for sent in sent_rx {
+        read_sync(&mut r, &mut frame)?;
+        if sent >= measured {
+            latencies.push(sent.elapsed());
+        }
+        credits_tx.send(()).unwrap_or(());
+    }
+    writer
+        .join()
+        .map_err(|_| "Thread join failed.")??;
+    Ok(Latencies::new(measured.elapsed(), latencies))
+}
+
+/// Like `server_test1_sync`, on an async backend.
+pub async fn server_test1_async<S>(server: S) -> Rs<()>
+where
+    S: AsyncServer,
+    <S as AsyncServer>::Client: Unpin,
+{
+    let mut c = server.accept_client_async().await?;
+    let mut frame = Vec::new();
+    while read_frame_async(&mut c, &mut frame).await? {
+        c.write_all(&frame).await?;
+    }
+    c.close().await?;
+    Ok(())
 }
 
-pub fn server_test2_sync<S>(server: S) -> Rs<f64>
+/// Like `client_test1_sync`, on an async backend.
+pub async fn client_test1_async<C>(mut client: C, params: &Params) -> Rs<()>
 where
-    S: 'static + Server + Send,
+    C: AsyncClient + Unpin,
+{
+    for &size in params.bufsiz.iter() {
+        let latencies = ping_pong_async(&mut client, params, size).await?;
+        println!("{} bytes: {}", size, latencies);
+    }
+    // some transports drop what is still in flight otherwise
+    client.close().await?;
+    Ok(())
+}
+
+async fn ping_pong_async<C>(client: &mut C, params: &Params, size: usize) -> Rs<Latencies>
+where
+    C: AsyncClient + Unpin,
+{
+    let (mut r, mut w) = client.split();
+    let (credits_tx, mut credits_rx) = mpsc::unbounded();
+    let (sent_tx, mut sent_rx) = mpsc::unbounded();
+    for _ in 0..params.depth {
+        credits_tx.unbounded_send(())?;
+    }
+    let measured = Instant::now() + params.warmup();
+    let end = measured + params.duration();
+    let writer = async move {
+        let frame = new_frame(size);
+        while credits_rx.next().await.is_some() && Instant::now() < end {
+            let sent = Instant::now();
+            w.write_all(&frame).await?;
+            if sent_tx.unbounded_send(sent).is_err() {
+                break;
+            }
+        }
+        Ok::<_, io::Error>(())
+    };
+    let reader = async move {
+        let mut latencies = Vec::new();
+        let mut frame = Vec::new();
+        while let Some(sent) = sent_rx.next().await {
+            read_async(&mut r, &mut frame).await?;
+            if sent >= measured {
+                latencies.push(sent.elapsed());
+            }
+            credits_tx.unbounded_send(()).unwrap_or(());
+        }
+        Ok::<_, io::Error>(latencies)
+    };
+    let (written, latencies) = futures::join!(writer, reader);
+    written?;
+    Ok(Latencies::new(measured.elapsed(), latencies?))
+}
+
+/// Hand a frame of each payload size in turn to every client that
+/// connects, and count how many of them send one back.
+pub fn server_test2_sync<S>(server: S, params: &Params) -> Rs<()>
+where
+    S: Server,
     <S as Server>::Client: 'static + Send,
 {
-    testcase(move |ready, quit| {
-        // synchronization phase
-        {
-            let mut c = server.accept_client().ok()?;
-            write_sync(&mut c).ok()?;
-            read_sync(&mut c).ok()?;
-        }
-        // first client connected, proceed with test
-        ready.send(()).ok()?;
-        let counter = Arc::new(AtomicU64::new(0));
-        while !quit.load(Ordering::Relaxed) {
-            match server.accept_client() {
-                Ok(mut c) => {
-                    let counter = Arc::clone(&counter);
-                    thread::spawn(move || {
-                        let _ = write_sync(&mut c);
-                        let _ = read_sync(&mut c);
-                        counter.fetch_add(1, Ordering::Relaxed);
-                    });
-                },
-                _ => continue,
-            };
-        }
-        Some(counter.load(Ordering::Relaxed))
-    })
-    .map(ops_per_sec)
+    for &size in params.bufsiz.iter() {
+        let ops = testcase(params, |ready, state| {
+            // synchronization phase
+            {
+                let mut c = server.accept_client().ok()?;
+                write_sync(&mut c, size).ok()?;
+                read_sync(&mut c, &mut Vec::new()).ok()?;
+            }
+            // first client connected, proceed with test
+            ready.send(()).ok()?;
+            while !state.quit.load(Ordering::Relaxed) {
+                match server.accept_client() {
+                    Ok(mut c) => {
+                        let state = Arc::clone(&state);
+                        thread::spawn(move || {
+                            let _ = write_sync(&mut c, size);
+                            let _ = read_sync(&mut c, &mut Vec::new());
+                            state.ops.fetch_add(1, Ordering::Relaxed);
+                        });
+                    },
+                    _ => continue,
+                };
+            }
+            Some(())
+        })?;
+        println!("{} bytes: {} requests per second", size, ops_per_sec(params, ops));
+    }
+    Ok(())
 }
 
+/// Connect to the server over and over, sending back the frame
+/// it hands over each time.
 pub fn client_test2_sync<C, F>(f: F) -> Rs<()>
 where
     C: 'static + Client + Send,
     F: Fn() -> Rs<C>,
 {
+    let mut frame = Vec::new();
     while let Ok(mut c) = f() {
-        read_sync(&mut c)?;
-        write_sync(&mut c)?;
+        read_sync(&mut c, &mut frame)?;
+        c.write_all(&frame)?;
     }
     Ok(())
 }
 
-pub async fn server_test2_async<S, R>(_runtime: R, server: S) -> Rs<f64>
+pub async fn server_test2_async<S, R>(_runtime: R, server: S, params: &Params) -> Rs<()>
 where
     R: runtime::Runtime,
-    S: 'static + AsyncServer + Send + Sync + Unpin,
+    S: AsyncServer + Sync + Unpin,
     <S as AsyncServer>::Client: 'static + Send + Unpin,
 {
-    testcase_async(_runtime, move |ready, quit| async move {
-        // synchronization phase
-        {
-            let mut c = server.accept_client_async().await.ok()?;
-            write_async(&mut c).await.ok()?;
-            read_async(&mut c).await.ok()?;
-        }
-        // first client connected, proceed with test
-        ready.send(()).ok()?;
-        let counter = Arc::new(AtomicU64::new(0));
-        while !quit.load(Ordering::Relaxed) {
-            match server.accept_client_async().await {
-                Ok(mut c) => {
-                    let counter = Arc::clone(&counter);
-                    R::spawn(async move {
-                        let _ = write_async(&mut c).await;
-                        let _ = read_async(&mut c).await;
-                        counter.fetch_add(1, Ordering::Relaxed);
-                        Some(0)
-                    });
-                },
-                _ => continue,
-            };
-        }
-        Some(counter.load(Ordering::Relaxed))
-    })
-    .await
-    .map(ops_per_sec)
+    let server = &server;
+    for &size in params.bufsiz.iter() {
+        let ops = testcase_async(params, |ready, state| async move {
+            // synchronization phase
+            {
+                let mut c = server.accept_client_async().await.ok()?;
+                write_async(&mut c, size).await.ok()?;
+                read_async(&mut c, &mut Vec::new()).await.ok()?;
+            }
+            // first client connected, proceed with test
+            ready.send(()).ok()?;
+            while !state.quit.load(Ordering::Relaxed) {
+                match server.accept_client_async().await {
+                    Ok(mut c) => {
+                        let state = Arc::clone(&state);
+                        drop(R::spawn(async move {
+                            let _ = write_async(&mut c, size).await;
+                            let _ = read_async(&mut c, &mut Vec::new()).await;
+                            state.ops.fetch_add(1, Ordering::Relaxed);
+                            Some(0)
+                        }));
+                    },
+                    _ => continue,
+                };
+            }
+            Some(())
+        })
+        .await?;
+        println!("{} bytes: {} requests per second", size, ops_per_sec(params, ops));
+    }
+    Ok(())
 }
 
 pub fn client_test2_async<R, C, N, F>(_runtime: R, f: F) -> Rs<()>
@@ -137,14 +262,15 @@ where
 {
     R::block_on(async move {
         // limit number of active connections
-        let (mut tx, mut rx) = mpsc::channel(100);
-        R::spawn(async move {
+        let (mut tx, mut rx) = mpsc::channel::<C>(100);
+        drop(R::spawn(async move {
+            let mut frame = Vec::new();
             while let Some(mut c) = rx.next().await {
-                read_async(&mut c).await.ok()?;
-                write_async(&mut c).await.ok()?;
+                read_async(&mut c, &mut frame).await.ok()?;
+                c.write_all(&frame).await.ok()?;
             }
             Some(0)
-        });
+        }));
         while let Ok(c) = f().await {
             tx.send(c).await?;
         }
@@ -152,64 +278,757 @@ where
     })
 }
 
-fn read_sync<R: Read>(mut r: R) -> Rs<()> {
-    let mut buf = [0_u8; params::BUFSIZ];
-    r.read(&mut buf[..])?;
+/// Like `server_test1_sync`, over completion-based I/O.
+pub async fn server_test1_completion<S: CompletionServer>(server: S) -> Rs<()> {
+    let c = server.accept_client_completion().await?;
+    let mut frame = Vec::new();
+    loop {
+        let (read, f) = read_frame_completion(&c, frame).await;
+        if !read? {
+            return Ok(());
+        }
+        let (written, f) = c.write_all_owned(f).await;
+        written?;
+        frame = f;
+    }
+}
+
+/// Like `client_test1_sync`, over completion-based I/O.
+pub async fn client_test1_completion<C: CompletionClient>(client: C, params: &Params) -> Rs<()> {
+    for &size in params.bufsiz.iter() {
+        let latencies = ping_pong_completion(&client, params, size).await?;
+        println!("{} bytes: {}", size, latencies);
+    }
     Ok(())
 }
 
-fn write_sync<W: Write>(mut w: W) -> Rs<()> {
-    let mut buf = [0_u8; params::BUFSIZ];
-    w.write(&mut buf[..])?;
+async fn ping_pong_completion<C: CompletionClient>(client: &C, params: &Params, size: usize) -> Rs<Latencies> {
+    let (credits_tx, mut credits_rx) = mpsc::unbounded();
+    let (sent_tx, mut sent_rx) = mpsc::unbounded();
+    for _ in 0..params.depth {
+        credits_tx.unbounded_send(())?;
+    }
+    let measured = Instant::now() + params.warmup();
+    let end = measured + params.duration();
+    let writer = async move {
+        let mut frame = new_frame(size);
+        while credits_rx.next().await.is_some() && Instant::now() < end {
+            let sent = Instant::now();
+            let (written, f) = client.write_all_owned(frame).await;
+            written?;
+            frame = f;
+            if sent_tx.unbounded_send(sent).is_err() {
+                break;
+            }
+        }
+        Ok::<_, io::Error>(())
+    };
+    let reader = async move {
+        let mut latencies = Vec::new();
+        let mut frame = Vec::new();
+        while let Some(sent) = sent_rx.next().await {
+            let (read, f) = read_frame_completion(client, frame).await;
+            if !read? {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            frame = f;
+            if sent >= measured {
+                latencies.push(sent.elapsed());
+            }
+            credits_tx.unbounded_send(()).unwrap_or(());
+        }
+        Ok::<_, io::Error>(latencies)
+    };
+    let (written, latencies) = futures::join!(writer, reader);
+    written?;
+    Ok(Latencies::new(measured.elapsed(), latencies?))
+}
+
+/// Like `server_test2_sync`, over completion-based I/O.
+pub async fn server_test2_completion<S, R>(_runtime: R, server: S, params: &Params) -> Rs<()>
+where
+    R: runtime::LocalRuntime,
+    S: CompletionServer,
+    <S as CompletionServer>::Client: 'static,
+{
+    let server = &server;
+    for &size in params.bufsiz.iter() {
+        let ops = testcase_async(params, |ready, state| async move {
+            // synchronization phase
+            {
+                let c = server.accept_client_completion().await.ok()?;
+                c.write_all_owned(new_frame(size)).await.0.ok()?;
+                read_frame_completion(&c, Vec::new()).await.0.ok()?;
+            }
+            // first client connected, proceed with test
+            ready.send(()).ok()?;
+            while !state.quit.load(Ordering::Relaxed) {
+                match server.accept_client_completion().await {
+                    Ok(c) => {
+                        let state = Arc::clone(&state);
+                        drop(R::spawn_local(async move {
+                            let _ = c.write_all_owned(new_frame(size)).await;
+                            let _ = read_frame_completion(&c, Vec::new()).await;
+                            state.ops.fetch_add(1, Ordering::Relaxed);
+                            Some(0)
+                        }));
+                    },
+                    _ => continue,
+                };
+            }
+            Some(())
+        })
+        .await?;
+        println!("{} bytes: {} requests per second", size, ops_per_sec(params, ops));
+    }
     Ok(())
 }
 
-async fn read_async<R: AsyncRead + Unpin>(mut r: R) -> Rs<()> {
-    let mut buf = [0_u8; params::BUFSIZ];
-    r.read(&mut buf[..]).await?;
+/// Like `client_test2_sync`, over completion-based I/O.
+pub fn client_test2_completion<R, C, N, F>(_runtime: R, f: F) -> Rs<()>
+where
+    R: runtime::LocalRuntime,
+    C: 'static + CompletionClient,
+    N: Future<Output = Rs<C>>,
+    F: Fn() -> N,
+{
+    R::block_on(async move {
+        // limit number of active connections
+        let (mut tx, mut rx) = mpsc::channel::<C>(100);
+        drop(R::spawn_local(async move {
+            let mut frame = Vec::new();
+            while let Some(c) = rx.next().await {
+                let (read, f) = read_frame_completion(&c, frame).await;
+                read.ok()?;
+                let (written, f) = c.write_all_owned(f).await;
+                written.ok()?;
+                frame = f;
+            }
+            Some(0)
+        }));
+        while let Ok(c) = f().await {
+            tx.send(c).await?;
+        }
+        Ok(())
+    })
+}
+
+/// Run the nodes of a mesh this process is in charge of. Every node
+/// connects to each of the others, and broadcasts frames of each
+/// payload size to all of them, while reading theirs.
+pub fn node_test3_sync<S>(params: &Params, mesh: &'static Mesh) -> Rs<()>
+where
+    S: Server + Sync,
+    <S as Server>::Client: Client<Addr = &'static str> + Send,
+{
+    let servers = mesh.own
+        .iter()
+        .map(|&id| S::listen_clients(&mesh.listen[id]))
+        .collect::<io::Result<Vec<_>>>()?;
+    for &size in params.bufsiz.iter() {
+        let counts = thread::scope(|s| {
+            let nodes: Vec<_> = mesh.own
+                .iter()
+                .zip(&servers)
+                .map(|(&id, server)| s.spawn(move || broadcast_sync(server, params, mesh, id, size)))
+                .collect();
+            nodes
+                .into_iter()
+                .map(|node| node.join().map_err(|_| "Thread join failed.")?.map_err(Into::into))
+                .collect::<Rs<Vec<_>>>()
+        })?;
+        print_counts(mesh, size, counts);
+    }
+    Ok(())
+}
+
+fn broadcast_sync<S>(server: &S, params: &Params, mesh: &'static Mesh, id: usize, size: usize) -> io::Result<Counts>
+where
+    S: Server + Sync,
+    <S as Server>::Client: Client<Addr = &'static str> + Send,
+{
+    let traffic = &Traffic::default();
+    thread::scope(|s| {
+        // accepted as the peers connect, as some backends only finish
+        // connecting once the other end reads
+        let receiver = s.spawn(move || -> io::Result<()> {
+            for _ in 1..params.nodes {
+                let mut c = server.accept_client()?;
+                s.spawn(move || {
+                    let mut frame = Vec::new();
+                    loop {
+                        match read_frame_sync(&mut c, &mut frame) {
+                            Ok(true) => traffic.received.fetch_add(1, Ordering::Relaxed),
+                            Ok(false) => return,
+                            Err(_) => return traffic.broken(),
+                        };
+                    }
+                });
+            }
+            Ok(())
+        });
+        let mut peers = Vec::new();
+        for addr in peers_of(mesh, id) {
+            let c = connect_sync::<<S as Server>::Client>(addr)?;
+            let mut drain = c.try_clone()?;
+            s.spawn(move || io::copy(&mut drain, &mut io::sink()));
+            peers.push(c);
+        }
+        let mut window = Window::new(params, traffic);
+        let frame = new_frame(size);
+        let sent = (|| {
+            while window.tick(traffic) {
+                for c in peers.iter_mut() {
+                    c.write_all(&frame)?;
+                    traffic.sent.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            Ok(())
+        })();
+        // the peers' reads end, and then so do the drains, or the
+        // scope would wait on them for good
+        let closed = peers.iter().map(Client::shutdown).fold(Ok(()), io::Result::and);
+        sent.and(closed)?;
+        receiver.join().map_err(|_| io::Error::other("Thread join failed."))??;
+        Ok(window.counts(traffic))
+    })
+}
+
+/// Like `node_test3_sync`, on an async backend.
+pub async fn node_test3_async<S, R>(_runtime: R, params: &'static Params, mesh: &'static Mesh) -> Rs<()>
+where
+    R: runtime::Runtime,
+    S: 'static + AsyncServer + Sync,
+    <S as AsyncServer>::Client: 'static + AsyncClient<Addr = &'static str> + Send + Unpin,
+{
+    let mut servers = Vec::new();
+    for &id in mesh.own.iter() {
+        servers.push(Arc::new(S::listen_clients_async(&mesh.listen[id]).await?));
+    }
+    for &size in params.bufsiz.iter() {
+        let mut nodes = Vec::new();
+        for (&id, server) in mesh.own.iter().zip(&servers) {
+            let (tx, rx) = oneshot::channel();
+            let server = Arc::clone(server);
+            drop(R::spawn(async move {
+                tx.send(broadcast_async::<S, R>(server, params, mesh, id, size).await).ok()?;
+                Some(0)
+            }));
+            nodes.push(rx);
+        }
+        let mut counts = Vec::new();
+        for node in nodes {
+            counts.push(node.await??);
+        }
+        print_counts(mesh, size, counts);
+    }
     Ok(())
 }
 
-async fn write_async<W: AsyncWrite + Unpin>(mut w: W) -> Rs<()> {
-    let mut buf = [0_u8; params::BUFSIZ];
-    w.write(&mut buf[..]).await?;
+async fn broadcast_async<S, R>(server: Arc<S>, params: &'static Params, mesh: &'static Mesh, id: usize, size: usize) -> io::Result<Counts>
+where
+    R: runtime::Runtime,
+    S: 'static + AsyncServer + Sync,
+    <S as AsyncServer>::Client: 'static + AsyncClient<Addr = &'static str> + Send + Unpin,
+{
+    let traffic = Arc::new(Traffic::default());
+    let mut tasks = Vec::new();
+    let receiver = {
+        let traffic = Arc::clone(&traffic);
+        R::spawn(async move {
+            let mut readers = Vec::new();
+            for _ in 1..params.nodes {
+                let mut c = server.accept_client_async().await.ok()?;
+                let traffic = Arc::clone(&traffic);
+                readers.push(R::spawn(async move {
+                    let mut frame = Vec::new();
+                    loop {
+                        match read_frame_async(&mut c, &mut frame).await {
+                            Ok(true) => traffic.received.fetch_add(1, Ordering::Relaxed),
+                            Ok(false) => return Some(0),
+                            Err(_) => {
+                                traffic.broken();
+                                return Some(0);
+                            }
+                        };
+                    }
+                }));
+            }
+            for reader in readers {
+                reader.await?;
+            }
+            Some(0)
+        })
+    };
+    let mut peers = Vec::new();
+    for addr in peers_of(mesh, id) {
+        let c = connect_async::<<S as AsyncServer>::Client>(addr).await?;
+        let (mut drain, w) = c.split();
+        tasks.push(R::spawn(async move {
+            let _ = futures::io::copy(&mut drain, &mut futures::io::sink()).await;
+            Some(0)
+        }));
+        peers.push(w);
+    }
+    let mut window = Window::new(params, &traffic);
+    let frame = new_frame(size);
+    while window.tick(&traffic) {
+        for w in peers.iter_mut() {
+            w.write_all(&frame).await?;
+            traffic.sent.fetch_add(1, Ordering::Relaxed);
+        }
+        yield_now().await;
+    }
+    for w in peers.iter_mut() {
+        w.close().await?;
+    }
+    tasks.push(receiver);
+    for task in tasks {
+        task.await.ok_or_else(|| io::Error::other("Lost a peer."))?;
+    }
+    Ok(window.counts(&traffic))
+}
+
+/// Like `node_test3_sync`, over completion-based I/O.
+pub async fn node_test3_completion<S, R>(_runtime: R, params: &'static Params, mesh: &'static Mesh) -> Rs<()>
+where
+    R: runtime::LocalRuntime,
+    S: 'static + CompletionServer,
+    <S as CompletionServer>::Client: 'static + CompletionClient<Addr = &'static str>,
+{
+    let mut servers = Vec::new();
+    for &id in mesh.own.iter() {
+        servers.push(Rc::new(S::listen_clients_completion(&mesh.listen[id]).await?));
+    }
+    for &size in params.bufsiz.iter() {
+        let mut nodes = Vec::new();
+        for (&id, server) in mesh.own.iter().zip(&servers) {
+            let (tx, rx) = oneshot::channel();
+            let server = Rc::clone(server);
+            drop(R::spawn_local(async move {
+                tx.send(broadcast_completion::<S, R>(server, params, mesh, id, size).await).ok()?;
+                Some(0)
+            }));
+            nodes.push(rx);
+        }
+        let mut counts = Vec::new();
+        for node in nodes {
+            counts.push(node.await??);
+        }
+        print_counts(mesh, size, counts);
+    }
     Ok(())
 }
 
-fn testcase<F>(job: F) -> Rs<u64>
+async fn broadcast_completion<S, R>(server: Rc<S>, params: &'static Params, mesh: &'static Mesh, id: usize, size: usize) -> io::Result<Counts>
 where
-    F: 'static + Send + FnOnce(SyncSender<()>, Arc<AtomicBool>) -> Option<u64>
+    R: runtime::LocalRuntime,
+    S: 'static + CompletionServer,
+    <S as CompletionServer>::Client: 'static + CompletionClient<Addr = &'static str>,
+{
+    let traffic = Arc::new(Traffic::default());
+    let receiver = {
+        let traffic = Arc::clone(&traffic);
+        R::spawn_local(async move {
+            let mut readers = Vec::new();
+            for _ in 1..params.nodes {
+                let c = server.accept_client_completion().await.ok()?;
+                let traffic = Arc::clone(&traffic);
+                readers.push(R::spawn_local(async move {
+                    let mut frame = Vec::new();
+                    loop {
+                        let (read, f) = read_frame_completion(&c, frame).await;
+                        match read {
+                            Ok(true) => traffic.received.fetch_add(1, Ordering::Relaxed),
+                            Ok(false) => return Some(0),
+                            Err(_) => {
+                                traffic.broken();
+                                return Some(0);
+                            }
+                        };
+                        frame = f;
+                    }
+                }));
+            }
+            for reader in readers {
+                reader.await?;
+            }
+            Some(0)
+        })
+    };
+    // nothing is written back over these, so they need no draining,
+    // and are closed when dropped
+    let mut peers = Vec::new();
+    for addr in peers_of(mesh, id) {
+        peers.push(connect_completion::<<S as CompletionServer>::Client>(addr).await?);
+    }
+    let mut window = Window::new(params, &traffic);
+    let mut frame = new_frame(size);
+    while window.tick(&traffic) {
+        for c in peers.iter() {
+            let (written, f) = c.write_all_owned(frame).await;
+            written?;
+            frame = f;
+            traffic.sent.fetch_add(1, Ordering::Relaxed);
+        }
+        yield_now().await;
+    }
+    drop(peers);
+    receiver.await.ok_or_else(|| io::Error::other("Lost a peer."))?;
+    Ok(window.counts(&traffic))
+}
+
+fn print_counts(mesh: &Mesh, size: usize, counts: Vec<Counts>) {
+    for (id, counts) in mesh.own.iter().zip(counts) {
+        println!("node {}: {} bytes: {}", id, size, counts);
+    }
+}
+
+// where node `id` sends its frames to
+fn peers_of(mesh: &'static Mesh, id: usize) -> impl Iterator<Item = &'static str> {
+    mesh.connect
+        .iter()
+        .enumerate()
+        .filter(move |&(peer, _)| peer != id)
+        .map(|(_, addr)| addr.as_str())
+}
+
+// the nodes of a mesh may be started one after the other, so
+// a peer that isn't listening yet is given some time to
+fn connect_sync<C: Client<Addr = &'static str>>(addr: &'static str) -> io::Result<C> {
+    let mut tries = 1;
+    loop {
+        match C::connect_server(addr) {
+            Err(e) if not_listening(&e) && tries < CONNECT_TRIES => {
+                thread::sleep(CONNECT_INTERVAL);
+                tries += 1;
+            },
+            result => return result,
+        }
+    }
+}
+
+async fn connect_async<C: AsyncClient<Addr = &'static str>>(addr: &'static str) -> io::Result<C> {
+    let mut tries = 1;
+    loop {
+        match C::connect_server_async(addr).await {
+            Err(e) if not_listening(&e) && tries < CONNECT_TRIES => {
+                Delay::new(CONNECT_INTERVAL).await;
+                tries += 1;
+            },
+            result => return result,
+        }
+    }
+}
+
+async fn connect_completion<C: CompletionClient<Addr = &'static str>>(addr: &'static str) -> io::Result<C> {
+    let mut tries = 1;
+    loop {
+        match C::connect_server_completion(addr).await {
+            Err(e) if not_listening(&e) && tries < CONNECT_TRIES => {
+                Delay::new(CONNECT_INTERVAL).await;
+                tries += 1;
+            },
+            result => return result,
+        }
+    }
+}
+
+/// Run a client until it gets through to its server, which may
+/// not be listening yet, e.g. as it starts alongside it.
+pub fn until_listening<F: FnMut() -> Rs<()>>(mut client: F) -> Rs<()> {
+    let mut tries = 1;
+    loop {
+        match client() {
+            Err(e) if e.downcast_ref().is_some_and(not_listening) && tries < CONNECT_TRIES => {
+                thread::sleep(CONNECT_INTERVAL);
+                tries += 1;
+            },
+            result => return result,
+        }
+    }
+}
+
+const CONNECT_TRIES: usize = 100;
+pub const CONNECT_INTERVAL: Duration = Duration::from_millis(100);
+
+fn not_listening(e: &io::Error) -> bool {
+    // a Unix domain socket that doesn't exist yet is not found
+    matches!(e.kind(), io::ErrorKind::ConnectionRefused | io::ErrorKind::NotFound)
+}
+
+// let the other tasks of the runtime have a turn, as writes that
+// never block would keep them waiting otherwise
+async fn yield_now() {
+    let mut yielded = false;
+    futures::future::poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
+
+// a frame is the length of its payload, as a big endian u32,
+// followed by the payload itself
+fn new_frame(size: usize) -> Vec<u8> {
+    let mut frame = vec![0; 4 + size];
+    frame[..4].copy_from_slice(&(size as u32).to_be_bytes());
+    frame
+}
+
+// read a whole frame into `frame`, header included, so it can be
+// echoed back as is; false if the peer hung up before sending one
+fn read_frame_sync<R: Read>(mut r: R, frame: &mut Vec<u8>) -> io::Result<bool> {
+    let mut header = [0; 4];
+    match r.read_exact(&mut header) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+        result => result?,
+    }
+    let len = u32::from_be_bytes(header) as usize;
+    frame.clear();
+    frame.extend_from_slice(&header);
+    frame.resize(4 + len, 0);
+    r.read_exact(&mut frame[4..])?;
+    Ok(true)
+}
+
+async fn read_frame_async<R: AsyncRead + Unpin>(mut r: R, frame: &mut Vec<u8>) -> io::Result<bool> {
+    let mut header = [0; 4];
+    match r.read_exact(&mut header).await {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+        result => result?,
+    }
+    let len = u32::from_be_bytes(header) as usize;
+    frame.clear();
+    frame.extend_from_slice(&header);
+    frame.resize(4 + len, 0);
+    r.read_exact(&mut frame[4..]).await?;
+    Ok(true)
+}
+
+async fn read_frame_completion<C: CompletionClient>(c: &C, mut frame: Vec<u8>) -> (io::Result<bool>, Vec<u8>) {
+    frame.clear();
+    let (result, frame) = c.read_exact_owned(frame, 4).await;
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && frame.is_empty() => return (Ok(false), frame),
+        Err(e) => return (Err(e), frame),
+        Ok(()) => (),
+    }
+    let len = u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]) as usize;
+    let (result, frame) = c.read_exact_owned(frame, 4 + len).await;
+    (result.map(|()| true), frame)
+}
+
+fn read_sync<R: Read>(r: R, frame: &mut Vec<u8>) -> io::Result<()> {
+    if read_frame_sync(r, frame)? {
+        Ok(())
+    } else {
+        Err(io::ErrorKind::UnexpectedEof.into())
+    }
+}
+
+fn write_sync<W: Write>(mut w: W, size: usize) -> io::Result<()> {
+    w.write_all(&new_frame(size))
+}
+
+async fn read_async<R: AsyncRead + Unpin>(r: R, frame: &mut Vec<u8>) -> io::Result<()> {
+    if read_frame_async(r, frame).await? {
+        Ok(())
+    } else {
+        Err(io::ErrorKind::UnexpectedEof.into())
+    }
+}
+
+async fn write_async<W: AsyncWrite + Unpin>(mut w: W, size: usize) -> io::Result<()> {
+    w.write_all(&new_frame(size)).await
+}
+
+// shared by a test case and everything it runs
+#[derive(Default)]
+struct State {
+    quit: AtomicBool,
+    ops: AtomicU64,
+}
+
+// what a test 3 node has sent and received so far
+#[derive(Default)]
+struct Traffic {
+    sent: AtomicU64,
+    received: AtomicU64,
+    // connections the node stopped reading from, as they failed
+    broken: AtomicU64,
+}
+
+impl Traffic {
+    fn broken(&self) {
+        self.broken.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> (Instant, u64, u64) {
+        (Instant::now(), self.sent.load(Ordering::Relaxed), self.received.load(Ordering::Relaxed))
+    }
+}
+
+// when a test 3 node is measured, which starts once it is connected
+struct Window {
+    measured: Instant,
+    end: Instant,
+    first: Option<(Instant, u64, u64)>,
+}
+
+impl Window {
+    fn new(params: &Params, traffic: &Traffic) -> Self {
+        let measured = Instant::now() + params.warmup();
+        let mut window = Window { measured, end: measured + params.duration(), first: None };
+        window.tick(traffic);
+        window
+    }
+
+    // false once the test is over
+    fn tick(&mut self, traffic: &Traffic) -> bool {
+        let now = Instant::now();
+        if self.first.is_none() && now >= self.measured {
+            self.first = Some(traffic.snapshot());
+        }
+        now < self.end
+    }
+
+    fn counts(self, traffic: &Traffic) -> Counts {
+        let (end, sent, received) = traffic.snapshot();
+        let (start, sent_before, received_before) = self.first.unwrap_or((end, sent, received));
+        Counts {
+            elapsed: end - start,
+            sent: sent - sent_before,
+            received: received - received_before,
+            broken: traffic.broken.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// run `job` until the test is over, counting the operations it does
+// from the end of the warmup, which starts once it says it is ready
+fn testcase<F>(params: &Params, job: F) -> Rs<u64>
+where
+    F: FnOnce(SyncSender<()>, Arc<State>) -> Option<()>,
 {
     let (tx, rx) = sync_channel(0);
-    let quit = Arc::new(AtomicBool::new(false));
-    let quit_clone = quit.clone();
-    let handle = thread::spawn(|| job(tx, quit_clone));
-    rx.recv()?; // ready to start test
-    thread::sleep(params::TIME);
-    quit.store(true, Ordering::Relaxed);
-    handle
+    let state = Arc::new(State::default());
+    let (warmup, duration) = (params.warmup(), params.duration());
+    let timer = {
+        let state = Arc::clone(&state);
+        thread::spawn(move || {
+            rx.recv().ok()?; // ready to start test
+            thread::sleep(warmup);
+            let start = state.ops.load(Ordering::Relaxed);
+            thread::sleep(duration);
+            state.quit.store(true, Ordering::Relaxed);
+            Some(state.ops.load(Ordering::Relaxed) - start)
+        })
+    };
+    let done = job(tx, state);
+    let ops = timer
         .join()
-        .map_err(|_| "Thread join failed.".into())
-        .and_then(|opt| opt.ok_or_else(|| "Thread ran into a problem.".into()))
+        .map_err(|_| "Thread join failed.")?;
+    done.and(ops).ok_or_else(|| "Test ran into a problem.".into())
 }
 
-async fn testcase_async<F, R, T>(_runtime: R, job: F) -> Rs<runtime::TaskOutput>
+async fn testcase_async<F, T>(params: &Params, job: F) -> Rs<u64>
 where
-    R: runtime::Runtime,
-    T: 'static + Send + Future<Output = Option<runtime::TaskOutput>>,
-    F: 'static + Send + Unpin + FnOnce(Sender<()>, Arc<AtomicBool>) -> T,
+    T: Future<Output = Option<()>>,
+    F: FnOnce(Sender<()>, Arc<State>) -> T,
 {
     let (tx, rx) = oneshot::channel();
-    let quit = Arc::new(AtomicBool::new(false));
-    let quit_clone = Arc::clone(&quit);
-    let handle = R::spawn(job(tx, quit_clone));
-    rx.await?; // ready to start test
-    let timer = Delay::new(params::TIME);
-    timer.await;
-    quit.store(true, Ordering::Relaxed);
-    handle.await.ok_or_else(|| "Task join failed.".into())
-}
-
-fn ops_per_sec(ops: u64) -> f64 {
-    (ops as f64) / (params::SECS as f64)
+    let state = Arc::new(State::default());
+    // polled along with the job, so it needs nothing of the runtime
+    let timer = async {
+        rx.await.ok()?; // ready to start test
+        Delay::new(params.warmup()).await;
+        let start = state.ops.load(Ordering::Relaxed);
+        Delay::new(params.duration()).await;
+        state.quit.store(true, Ordering::Relaxed);
+        Some(state.ops.load(Ordering::Relaxed) - start)
+    };
+    let (done, ops) = futures::join!(job(tx, Arc::clone(&state)), timer);
+    done.and(ops).ok_or_else(|| "Test ran into a problem.".into())
+}
+
+fn ops_per_sec(params: &Params, ops: u64) -> f64 {
+    (ops as f64) / params.duration().as_secs_f64()
+}
+
+/// Frames a test 3 node sent to and received from its peers,
+/// while it was measured.
+pub struct Counts {
+    elapsed: Duration,
+    sent: u64,
+    received: u64,
+    broken: u64,
+}
+
+impl Counts {
+    pub fn sent_per_sec(&self) -> f64 {
+        self.sent as f64 / self.elapsed.as_secs_f64()
+    }
+
+    pub fn received_per_sec(&self) -> f64 {
+        self.received as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl fmt::Display for Counts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.2} frames sent, {:.2} received per second",
+            self.sent_per_sec(),
+            self.received_per_sec(),
+        )?;
+        if self.broken > 0 {
+            write!(f, ", {} connections broken", self.broken)?;
+        }
+        Ok(())
+    }
+}
+
+/// Round trip times of the requests of a test 1 client.
+pub struct Latencies {
+    elapsed: Duration,
+    sorted: Vec<Duration>,
+}
+
+impl Latencies {
+    fn new(elapsed: Duration, mut latencies: Vec<Duration>) -> Self {
+        latencies.sort_unstable();
+        Latencies { elapsed, sorted: latencies }
+    }
+
+    pub fn ops_per_sec(&self) -> f64 {
+        self.sorted.len() as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// The `p`-th percentile of the round trip times.
+    pub fn percentile(&self, p: f64) -> Duration {
+        if self.sorted.is_empty() {
+            return Duration::default();
+        }
+        let i = ((self.sorted.len() - 1) as f64 * p).round() as usize;
+        self.sorted[i]
+    }
+}
+
+impl fmt::Display for Latencies {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.2} requests per second, latency p50 {:?} p99 {:?} p999 {:?}",
+            self.ops_per_sec(),
+            self.percentile(0.50),
+            self.percentile(0.99),
+            self.percentile(0.999),
+        )
+    }
 }
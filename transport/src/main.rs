@@ -3,116 +3,362 @@ pub mod params;
 pub mod runtime;
 pub mod handlers;
 
+use std::fs;
+use std::thread;
+
+use clap::Parser;
+
 use nodes::tcp_sync;
 use nodes::tcp_tokio;
 use nodes::tcp_async_std;
+use nodes::tcp_io_uring;
+use nodes::tcp_tls_sync;
+use nodes::tcp_tls_tokio;
+use nodes::udp_tokio;
+use nodes::quic;
+use nodes::uds_sync;
+use nodes::uds_tokio;
 use nodes::{
     Client, Server,
     AsyncClient, AsyncServer,
+    CompletionClient, CompletionServer,
 };
+use params::{Backend, Mesh, Params, Role, RuntimeKind};
 use runtime::{
-    Runtime,
-    tokio::Runtime as TRuntime,
+    Runtime, LocalRuntime,
+    tokio::{Runtime as TRuntime, CurrentThread as TCRuntime},
     async_std::Runtime as ASRuntime,
+    smol::Runtime as SRuntime,
+    io_uring::Runtime as URuntime,
 };
 
 macro_rules! doit {
     ($f:expr) => { $f() }
 }
 
-fn main() {
-    let arg = match std::env::args().nth(1) {
-        Some(arg) => arg,
-        None => usage(),
-    };
-    let result = match std::env::var("TEST").as_ref().map(String::as_ref) {
-        Ok("1") => kind_1(&arg),
-        Ok("2") => kind_2(&arg),
-        _ => usage(),
+// evaluate `$e` with `$r` standing for the runtime picked to drive
+// the backend, once for each of them
+macro_rules! on_runtime {
+    ($params:expr, $r:ident => $e:expr) => {
+        match $params.runtime()? {
+            RuntimeKind::Tokio => {
+                use runtime::tokio::Runtime as $r;
+                $e
+            },
+            RuntimeKind::TokioCurrentThread => {
+                use runtime::tokio::CurrentThread as $r;
+                $e
+            },
+            RuntimeKind::AsyncStd => {
+                use runtime::async_std::Runtime as $r;
+                $e
+            },
+            RuntimeKind::Smol => {
+                use runtime::smol::Runtime as $r;
+                $e
+            },
+        }
     };
-    result.unwrap_or_else(|e| {
+}
+
+fn main() {
+    // the backends hold on to their addresses for the whole run
+    let params: &'static Params = Box::leak(Box::new(Params::parse()));
+    TRuntime::init(params.threads);
+    TCRuntime::init(params.threads);
+    ASRuntime::init(params.threads);
+    SRuntime::init(params.threads);
+    URuntime::init(params.threads);
+    run(params).unwrap_or_else(|e| {
         eprintln!("Something went wrong: {}", e);
         std::process::exit(1);
     });
 }
 
-fn kind_1(arg: &str) -> Result<(), Box<dyn std::error::Error>> {
-    match arg {
-        "tcp:sync:client" => doit!(|| {
-            let clients: Result<Vec<_>, _> = params::ADDRS
-                .iter()
-                .skip(1)
-                .map(|addr| tcp_sync::C::connect_server(addr))
-                .collect();
-            let ops = handlers::client_test1_sync(clients?)?;
-            println!("{} requests per second", ops);
-            Ok(())
-        }),
-        "tcp:sync:server" => doit!(|| {
-            let server = tcp_sync::S::listen_clients(params::LADDR)?;
+fn run(params: &'static Params) -> Result<(), Box<dyn std::error::Error>> {
+    if params.runtime.is_some() {
+        // even for a backend that would run on none
+        params.runtime()?;
+    }
+    match (params.role, params.test) {
+        (Role::Local, _) => local(params),
+        (_, 1) => kind_1(params),
+        (_, 2) => kind_2(params),
+        _ => kind_3(params),
+    }
+}
+
+// run every end of a test here, over loopback
+fn local(params: &'static Params) -> Result<(), Box<dyn std::error::Error>> {
+    let n = if params.test == 3 { params.nodes } else { 1 };
+    let addrs = params.backend.loopback(n)?;
+    let result = match params.test {
+        1 | 2 => {
+            let addr = addrs[0].clone();
+            let server = Params { role: Role::Server, listen: addr.clone(), ..params.clone() };
+            let client = Params { role: Role::Client, server: addr, ..params.clone() };
+            let server: &'static Params = Box::leak(Box::new(server));
+            let client: &'static Params = Box::leak(Box::new(client));
+            if params.test == 1 {
+                // the client measures, and the server is done once
+                // it hangs up
+                drop(thread::spawn(move || {
+                    // what goes wrong there, the client runs into
+                    let _ = kind_1(server);
+                }));
+                handlers::until_listening(|| kind_1(client))
+            } else {
+                // the server measures, while the client is started over
+                // whenever it can't connect, i.e. until the server listens
+                drop(thread::spawn(move || loop {
+                    let _ = kind_2(client);
+                    thread::sleep(handlers::CONNECT_INTERVAL);
+                }));
+                kind_2(server)
+            }
+        },
+        _ => nodes(params, Box::leak(Box::new(Mesh::local(addrs.clone())))),
+    };
+    if params.backend.is_unix() {
+        for path in addrs.iter() {
+            let _ = fs::remove_file(path);
+        }
+    }
+    result
+}
+
+fn kind_1(params: &'static Params) -> Result<(), Box<dyn std::error::Error>> {
+    match (params.backend, params.role) {
+        (Backend::TcpSync, Role::Client) => doit!(|| {
+            let client = tcp_sync::C::connect_server(&params.server)?;
+            handlers::client_test1_sync(client, params)
+        }),
+        (Backend::TcpSync, Role::Server) => doit!(|| {
+            let server = tcp_sync::S::listen_clients(&params.listen)?;
+            handlers::server_test1_sync(server)
+        }),
+        (Backend::TcpTokio, Role::Client) => on_runtime!(params, R => R::block_on(async {
+            let client = tcp_tokio::C::connect_server_async(&params.server).await?;
+            handlers::client_test1_async(client, params).await
+        })),
+        (Backend::TcpTokio, Role::Server) => on_runtime!(params, R => R::block_on(async {
+            let server = tcp_tokio::S::listen_clients_async(&params.listen).await?;
+            handlers::server_test1_async(server).await
+        })),
+        (Backend::TcpAsyncStd, Role::Client) => on_runtime!(params, R => R::block_on(async {
+            let client = tcp_async_std::C::connect_server_async(&params.server).await?;
+            handlers::client_test1_async(client, params).await
+        })),
+        (Backend::TcpAsyncStd, Role::Server) => on_runtime!(params, R => R::block_on(async {
+            let server = tcp_async_std::S::listen_clients_async(&params.listen).await?;
+            handlers::server_test1_async(server).await
+        })),
+        (Backend::TcpIoUring, Role::Client) => URuntime::block_on(async {
+            let client = tcp_io_uring::C::connect_server_completion(&params.server).await?;
+            handlers::client_test1_completion(client, params).await
+        }),
+        (Backend::TcpIoUring, Role::Server) => URuntime::block_on(async {
+            let server = tcp_io_uring::S::listen_clients_completion(&params.listen).await?;
+            handlers::server_test1_completion(server).await
+        }),
+        (Backend::TcpTlsSync, Role::Client) => doit!(|| {
+            let client = tcp_tls_sync::C::connect_server(&params.server)?;
+            handlers::client_test1_sync(client, params)
+        }),
+        (Backend::TcpTlsSync, Role::Server) => doit!(|| {
+            let server = tcp_tls_sync::S::listen_clients(&params.listen)?;
+            handlers::server_test1_sync(server)
+        }),
+        (Backend::TcpTlsTokio, Role::Client) => on_runtime!(params, R => R::block_on(async {
+            let client = tcp_tls_tokio::C::connect_server_async(&params.server).await?;
+            handlers::client_test1_async(client, params).await
+        })),
+        (Backend::TcpTlsTokio, Role::Server) => on_runtime!(params, R => R::block_on(async {
+            let server = tcp_tls_tokio::S::listen_clients_async(&params.listen).await?;
+            handlers::server_test1_async(server).await
+        })),
+        (Backend::UdpTokio, Role::Client) => on_runtime!(params, R => R::block_on(async {
+            let client = udp_tokio::C::connect_server_async(&params.server).await?;
+            handlers::client_test1_async(client, params).await
+        })),
+        (Backend::UdpTokio, Role::Server) => on_runtime!(params, R => R::block_on(async {
+            let server = udp_tokio::S::listen_clients_async(&params.listen).await?;
+            handlers::server_test1_async(server).await
+        })),
+        (Backend::Quic, Role::Client) => on_runtime!(params, R => R::block_on(async {
+            let client = quic::C::connect_server_async(&params.server).await?;
+            handlers::client_test1_async(client, params).await
+        })),
+        (Backend::Quic, Role::Server) => on_runtime!(params, R => R::block_on(async {
+            let server = quic::S::listen_clients_async(&params.listen).await?;
+            handlers::server_test1_async(server).await
+        })),
+        (Backend::UdsSync, Role::Client) => doit!(|| {
+            let client = uds_sync::C::connect_server(&params.server)?;
+            handlers::client_test1_sync(client, params)
+        }),
+        (Backend::UdsSync, Role::Server) => doit!(|| {
+            let server = uds_sync::S::listen_clients(&params.listen)?;
             handlers::server_test1_sync(server)
         }),
-        _ => invalid_backend(),
+        (Backend::UdsTokio, Role::Client) => on_runtime!(params, R => R::block_on(async {
+            let client = uds_tokio::C::connect_server_async(&params.server).await?;
+            handlers::client_test1_async(client, params).await
+        })),
+        (Backend::UdsTokio, Role::Server) => on_runtime!(params, R => R::block_on(async {
+            let server = uds_tokio::S::listen_clients_async(&params.listen).await?;
+            handlers::server_test1_async(server).await
+        })),
+        (_, Role::Node) => Err("Only test 3 runs nodes.".into()),
+        (_, Role::Local) => unreachable!("local runs are split up beforehand"),
     }
 }
 
-fn kind_2(arg: &str) -> Result<(), Box<dyn std::error::Error>> {
-    match arg {
-        "tcp:sync:client" => {
+fn kind_2(params: &'static Params) -> Result<(), Box<dyn std::error::Error>> {
+    match (params.backend, params.role) {
+        (Backend::TcpSync, Role::Client) => {
             handlers::client_test2_sync(|| {
-                let client = tcp_sync::C::connect_server(params::N2)?;
+                let client = tcp_sync::C::connect_server(&params.server)?;
                 Ok(client)
             })
         },
-        "tcp:sync:server" => doit!(|| {
-            let server = tcp_sync::S::listen_clients(params::LADDR)?;
-            let ops = handlers::server_test2_sync(server)?;
-            println!("{} requests per second", ops);
-            Ok(())
-        }),
-        "tcp:tokio:client" => {
-            handlers::client_test2_async(TRuntime, || async {
-                let client = tcp_tokio::C::connect_server_async(params::N2).await?;
+        (Backend::TcpSync, Role::Server) => doit!(|| {
+            let server = tcp_sync::S::listen_clients(&params.listen)?;
+            handlers::server_test2_sync(server, params)
+        }),
+        (Backend::TcpTokio, Role::Client) => on_runtime!(params, R => {
+            handlers::client_test2_async(R, || async {
+                let client = tcp_tokio::C::connect_server_async(&params.server).await?;
+                Ok(client)
+            })
+        }),
+        (Backend::TcpTokio, Role::Server) => on_runtime!(params, R => R::block_on(async {
+            let server = tcp_tokio::S::listen_clients_async(&params.listen).await?;
+            handlers::server_test2_async(R, server, params).await
+        })),
+        (Backend::TcpAsyncStd, Role::Client) => on_runtime!(params, R => {
+            handlers::client_test2_async(R, || async {
+                let client = tcp_async_std::C::connect_server_async(&params.server).await?;
+                Ok(client)
+            })
+        }),
+        (Backend::TcpAsyncStd, Role::Server) => on_runtime!(params, R => R::block_on(async {
+            let server = tcp_async_std::S::listen_clients_async(&params.listen).await?;
+            handlers::server_test2_async(R, server, params).await
+        })),
+        (Backend::TcpIoUring, Role::Client) => {
+            handlers::client_test2_completion(URuntime, || async {
+                let client = tcp_io_uring::C::connect_server_completion(&params.server).await?;
                 Ok(client)
             })
         },
-        "tcp:tokio:server" => TRuntime::block_on(async {
-            let server = tcp_tokio::S::listen_clients_async(params::LADDR).await?;
-            let ops = handlers::server_test2_async(TRuntime, server).await?;
-            println!("{} requests per second", ops);
-            Ok(())
-        }),
-        "tcp:async_std:client" => {
-            ASRuntime::init();
-            handlers::client_test2_async(ASRuntime, || async {
-                let client = tcp_async_std::C::connect_server_async(params::N2).await?;
+        (Backend::TcpIoUring, Role::Server) => URuntime::block_on(async {
+            let server = tcp_io_uring::S::listen_clients_completion(&params.listen).await?;
+            handlers::server_test2_completion(URuntime, server, params).await
+        }),
+        (Backend::TcpTlsSync, Role::Client) => {
+            handlers::client_test2_sync(|| {
+                let client = tcp_tls_sync::C::connect_server(&params.server)?;
                 Ok(client)
             })
         },
-        "tcp:async_std:server" => {
-            ASRuntime::init();
-            ASRuntime::block_on(async {
-                let server = tcp_async_std::S::listen_clients_async(params::LADDR).await?;
-                let ops = handlers::server_test2_async(ASRuntime, server).await?;
-                println!("{} requests per second", ops);
-                Ok(())
+        (Backend::TcpTlsSync, Role::Server) => doit!(|| {
+            let server = tcp_tls_sync::S::listen_clients(&params.listen)?;
+            handlers::server_test2_sync(server, params)
+        }),
+        (Backend::TcpTlsTokio, Role::Client) => on_runtime!(params, R => {
+            handlers::client_test2_async(R, || async {
+                let client = tcp_tls_tokio::C::connect_server_async(&params.server).await?;
+                Ok(client)
+            })
+        }),
+        (Backend::TcpTlsTokio, Role::Server) => on_runtime!(params, R => R::block_on(async {
+            let server = tcp_tls_tokio::S::listen_clients_async(&params.listen).await?;
+            handlers::server_test2_async(R, server, params).await
+        })),
+        (Backend::UdpTokio, Role::Client) => on_runtime!(params, R => {
+            handlers::client_test2_async(R, || async {
+                let client = udp_tokio::C::connect_server_async(&params.server).await?;
+                Ok(client)
+            })
+        }),
+        (Backend::UdpTokio, Role::Server) => on_runtime!(params, R => R::block_on(async {
+            let server = udp_tokio::S::listen_clients_async(&params.listen).await?;
+            handlers::server_test2_async(R, server, params).await
+        })),
+        (Backend::Quic, Role::Client) => on_runtime!(params, R => {
+            handlers::client_test2_async(R, || async {
+                let client = quic::C::connect_server_async(&params.server).await?;
+                Ok(client)
+            })
+        }),
+        (Backend::Quic, Role::Server) => on_runtime!(params, R => R::block_on(async {
+            let server = quic::S::listen_clients_async(&params.listen).await?;
+            handlers::server_test2_async(R, server, params).await
+        })),
+        (Backend::UdsSync, Role::Client) => {
+            handlers::client_test2_sync(|| {
+                let client = uds_sync::C::connect_server(&params.server)?;
+                Ok(client)
             })
         },
-        _ => invalid_backend(),
+        (Backend::UdsSync, Role::Server) => doit!(|| {
+            let server = uds_sync::S::listen_clients(&params.listen)?;
+            handlers::server_test2_sync(server, params)
+        }),
+        (Backend::UdsTokio, Role::Client) => on_runtime!(params, R => {
+            handlers::client_test2_async(R, || async {
+                let client = uds_tokio::C::connect_server_async(&params.server).await?;
+                Ok(client)
+            })
+        }),
+        (Backend::UdsTokio, Role::Server) => on_runtime!(params, R => R::block_on(async {
+            let server = uds_tokio::S::listen_clients_async(&params.listen).await?;
+            handlers::server_test2_async(R, server, params).await
+        })),
+        (_, Role::Node) => Err("Only test 3 runs nodes.".into()),
+        (_, Role::Local) => unreachable!("local runs are split up beforehand"),
     }
 }
 
-fn invalid_backend() -> Result<(), Box<dyn std::error::Error>> {
-    Err("Invalid backend; try \"help\".".into())
+fn kind_3(params: &'static Params) -> Result<(), Box<dyn std::error::Error>> {
+    if params.role != Role::Node {
+        return Err("Test 3 only runs nodes.".into());
+    }
+    nodes(params, Box::leak(Box::new(params.mesh()?)))
 }
 
-fn usage() -> ! {
-    eprintln!("Available backends:");
-    eprintln!("  - tcp:sync:{{client, server}}");
-    eprintln!("  - tcp:tokio:{{client, server}}");
-    eprintln!("  - tcp:async_std:{{client, server}}");
-    eprintln!("");
-    eprintln!("Available test kinds:");
-    eprintln!("  - 1 / 2");
-    std::process::exit(1)
+fn nodes(params: &'static Params, mesh: &'static Mesh) -> Result<(), Box<dyn std::error::Error>> {
+    match params.backend {
+        Backend::TcpSync => {
+            handlers::node_test3_sync::<tcp_sync::S>(params, mesh)
+        },
+        Backend::TcpTokio => on_runtime!(params, R => R::block_on(async {
+            handlers::node_test3_async::<tcp_tokio::S, _>(R, params, mesh).await
+        })),
+        Backend::TcpAsyncStd => on_runtime!(params, R => R::block_on(async {
+            handlers::node_test3_async::<tcp_async_std::S, _>(R, params, mesh).await
+        })),
+        Backend::TcpIoUring => URuntime::block_on(async {
+            handlers::node_test3_completion::<tcp_io_uring::S, _>(URuntime, params, mesh).await
+        }),
+        Backend::TcpTlsSync => {
+            handlers::node_test3_sync::<tcp_tls_sync::S>(params, mesh)
+        },
+        Backend::TcpTlsTokio => on_runtime!(params, R => R::block_on(async {
+            handlers::node_test3_async::<tcp_tls_tokio::S, _>(R, params, mesh).await
+        })),
+        Backend::UdpTokio => on_runtime!(params, R => R::block_on(async {
+            handlers::node_test3_async::<udp_tokio::S, _>(R, params, mesh).await
+        })),
+        Backend::Quic => on_runtime!(params, R => R::block_on(async {
+            handlers::node_test3_async::<quic::S, _>(R, params, mesh).await
+        })),
+        Backend::UdsSync => {
+            handlers::node_test3_sync::<uds_sync::S>(params, mesh)
+        },
+        Backend::UdsTokio => on_runtime!(params, R => R::block_on(async {
+            handlers::node_test3_async::<uds_tokio::S, _>(R, params, mesh).await
+        })),
+    }
 }
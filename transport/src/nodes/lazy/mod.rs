@@ -0,0 +1,110 @@
+//! Streams set up by their first read or write, e.g. through a
+//! handshake, so a server doesn't go through the setup of the
+//! connections it accepts one at a time.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Poll, Context, Waker};
+
+use futures::io::{AsyncRead, AsyncWrite};
+
+pub enum Lazy<T> {
+    // the setup only wakes whoever polled it last, so the others
+    // waiting on it, e.g. the halves of a split stream, are kept
+    Setup(Pin<Box<dyn Future<Output = io::Result<T>> + Send>>, Vec<Waker>),
+    Ready(Box<T>),
+    // the setup failed; the first poll to see it got its error,
+    // and those after it, e.g. by the other half, get its kind
+    Failed(io::ErrorKind),
+}
+
+impl<T> Lazy<T> {
+    pub fn new<F>(setup: F) -> Self
+    where
+        F: 'static + Send + Future<Output = io::Result<T>>,
+    {
+        Lazy::Setup(Box::pin(setup), Vec::new())
+    }
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<&mut T>> {
+        if let Lazy::Setup(setup, waiting) = self {
+            let result = setup.as_mut().poll(cx);
+            if result.is_ready() {
+                waiting.drain(..).for_each(Waker::wake);
+            }
+            match result {
+                Poll::Ready(Ok(stream)) => *self = Lazy::Ready(Box::new(stream)),
+                Poll::Ready(Err(e)) => {
+                    *self = Lazy::Failed(e.kind());
+                    return Poll::Ready(Err(e));
+                },
+                Poll::Pending => {
+                    if !waiting.iter().any(|w| w.will_wake(cx.waker())) {
+                        waiting.push(cx.waker().clone());
+                    }
+                    return Poll::Pending;
+                }
+            }
+        }
+        match self {
+            Lazy::Ready(stream) => Poll::Ready(Ok(stream)),
+            Lazy::Failed(kind) => Poll::Ready(Err(io::Error::new(*kind, "the stream's setup failed"))),
+            Lazy::Setup(..) => unreachable!(),
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Lazy<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8]
+    ) -> Poll<io::Result<usize>>
+    {
+        match self.get_mut().poll_ready(cx) {
+            Poll::Ready(Ok(stream)) => Pin::new(stream).poll_read(cx, buf),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Lazy<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8]
+    ) -> Poll<io::Result<usize>>
+    {
+        match self.get_mut().poll_ready(cx) {
+            Poll::Ready(Ok(stream)) => Pin::new(stream).poll_write(cx, buf),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>
+    ) -> Poll<io::Result<()>>
+    {
+        match self.get_mut().poll_ready(cx) {
+            Poll::Ready(Ok(stream)) => Pin::new(stream).poll_flush(cx),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_close(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>
+    ) -> Poll<io::Result<()>>
+    {
+        match self.get_mut().poll_ready(cx) {
+            Poll::Ready(Ok(stream)) => Pin::new(stream).poll_close(cx),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
@@ -6,11 +6,28 @@ use futures::io::{AsyncRead, AsyncWrite};
 pub mod tcp_sync;
 pub mod tcp_tokio;
 pub mod tcp_async_std;
+pub mod tcp_io_uring;
+pub mod tcp_tls_sync;
+pub mod tcp_tls_tokio;
+pub mod udp_tokio;
+pub mod quic;
+pub mod uds_sync;
+pub mod uds_tokio;
+pub mod tls;
+pub mod lazy;
 
 pub trait Client: Read + Write + Sized {
     type Addr;
 
     fn connect_server(addr: Self::Addr) -> io::Result<Self>;
+
+    /// Another handle to the same connection, e.g. to read
+    /// from it on one thread while writing on another.
+    fn try_clone(&self) -> io::Result<Self>;
+
+    /// Stop writing to the connection, so the peer reads to the end
+    /// of it, while it may still be read from.
+    fn shutdown(&self) -> io::Result<()>;
 }
 
 pub trait Server: Sized {
@@ -34,3 +51,27 @@ pub trait AsyncServer: Send + Sized {
     async fn listen_clients_async(addr: <<Self as AsyncServer>::Client as AsyncClient>::Addr) -> io::Result<Self>;
     async fn accept_client_async(&self) -> io::Result<Self::Client>;
 }
+
+/// A client over completion-based I/O, such as io_uring, where the
+/// kernel owns the buffer of an operation until it completes. Reads
+/// and writes take the buffer, and hand it back with the result.
+#[async_trait(?Send)]
+pub trait CompletionClient: Sized {
+    type Addr;
+
+    async fn connect_server_completion(addr: Self::Addr) -> io::Result<Self>;
+
+    /// Read into `buf` until it holds `len` bytes; reaching
+    /// the end of the stream first is an `UnexpectedEof`.
+    async fn read_exact_owned(&self, buf: Vec<u8>, len: usize) -> (io::Result<()>, Vec<u8>);
+
+    async fn write_all_owned(&self, buf: Vec<u8>) -> (io::Result<()>, Vec<u8>);
+}
+
+#[async_trait(?Send)]
+pub trait CompletionServer: Sized {
+    type Client: CompletionClient;
+
+    async fn listen_clients_completion(addr: <<Self as CompletionServer>::Client as CompletionClient>::Addr) -> io::Result<Self>;
+    async fn accept_client_completion(&self) -> io::Result<Self::Client>;
+}
@@ -0,0 +1,232 @@
+//! QUIC over quinn, with the certificates of the TLS backends. Each
+//! connection carries one bidirectional stream, which the client opens
+//! with a byte of its own, as a stream only reaches the peer once
+//! something is sent on it.
+
+use crate::nodes;
+use crate::nodes::lazy::Lazy;
+use crate::nodes::tls;
+
+use std::convert::{TryFrom, TryInto};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Poll, Context};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::io::{AsyncRead, AsyncWrite};
+use lazy_static::lazy_static;
+use quinn::{
+    ClientConfig, Connection, ConnectionError, Endpoint, RecvStream,
+    SendStream, ServerConfig, StoppedError, TransportConfig,
+};
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use tokio::net;
+use tokio::runtime::Handle;
+
+/// How long a connection goes without hearing from the peer before
+/// giving up on it, e.g. once the server is gone.
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    static ref CONFIGS: (ClientConfig, ServerConfig) = configs().expect("Failed to set up the QUIC configs.");
+}
+
+/// A connection, which the server sets up as it is first read
+/// from or written to, and a client before connecting returns.
+pub struct C(Lazy<Stream>);
+
+pub struct S(Endpoint);
+
+struct Stream {
+    send: SendStream,
+    recv: RecvStream,
+    conn: Connection,
+    // a client's own
+    endpoint: Option<Endpoint>,
+    // until the peer has read everything up to the end of the stream
+    closing: Option<Pin<Box<dyn Future<Output = io::Result<()>> + Send>>>,
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        if self.closing.is_some() {
+            return;
+        }
+        // like a TCP socket, deliver what was written in the background,
+        // as the connection goes with its last handle
+        let stopped = self.send.stopped();
+        let conn = self.conn.clone();
+        let endpoint = self.endpoint.take();
+        if let Ok(runtime) = Handle::try_current() {
+            drop(runtime.spawn(async move {
+                let _ = stopped.await;
+                conn.close(0u8.into(), b"");
+                drop(endpoint);
+            }));
+        }
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8]
+    ) -> Poll<io::Result<usize>>
+    {
+        Pin::new(&mut self.recv).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8]
+    ) -> Poll<io::Result<usize>>
+    {
+        AsyncWrite::poll_write(Pin::new(&mut self.send), cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>
+    ) -> Poll<io::Result<()>>
+    {
+        Pin::new(&mut self.send).poll_flush(cx)
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>
+    ) -> Poll<io::Result<()>>
+    {
+        if self.closing.is_none() {
+            self.send.finish()?;
+            let stopped = self.send.stopped();
+            let conn = self.conn.clone();
+            let endpoint = self.endpoint.clone();
+            self.closing = Some(Box::pin(async move {
+                match stopped.await {
+                    // the peer had read all it wanted, and hung up
+                    // without acknowledging the end of the stream
+                    Err(StoppedError::ConnectionLost(ConnectionError::ApplicationClosed(_))) => (),
+                    result => drop(result?),
+                }
+                // the stream is all there is to the connection
+                conn.close(0u8.into(), b"");
+                if let Some(endpoint) = endpoint {
+                    // so the server hears of it, should we exit next
+                    endpoint.wait_idle().await;
+                }
+                Ok(())
+            }));
+        }
+        self.closing.as_mut().unwrap().as_mut().poll(cx)
+    }
+}
+
+impl AsyncRead for C {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8]
+    ) -> Poll<io::Result<usize>>
+    {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for C {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8]
+    ) -> Poll<io::Result<usize>>
+    {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>
+    ) -> Poll<io::Result<()>>
+    {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>
+    ) -> Poll<io::Result<()>>
+    {
+        Pin::new(&mut self.0).poll_close(cx)
+    }
+}
+
+#[async_trait]
+impl nodes::AsyncClient for C {
+    type Addr = &'static str;
+
+    async fn connect_server_async(addr: Self::Addr) -> io::Result<Self> {
+        let addr = resolve(addr).await?;
+        let local = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let mut endpoint = Endpoint::client(local.parse().unwrap())?;
+        endpoint.set_default_client_config(CONFIGS.0.clone());
+        let conn = endpoint.connect(addr, tls::SERVER_NAME)
+            .map_err(io::Error::other)?
+            .await?;
+        let (mut send, recv) = conn.open_bi().await?;
+        send.write_all(&[0]).await?;
+        let stream = Stream { send, recv, conn, endpoint: Some(endpoint), closing: None };
+        Ok(C(Lazy::Ready(Box::new(stream))))
+    }
+}
+
+#[async_trait]
+impl nodes::AsyncServer for S {
+    type Client = C;
+
+    async fn listen_clients_async(addr: <<Self as nodes::AsyncServer>::Client as nodes::AsyncClient>::Addr) -> io::Result<Self> {
+        let addr = resolve(addr).await?;
+        Endpoint::server(CONFIGS.1.clone(), addr).map(S)
+    }
+
+    async fn accept_client_async(&self) -> io::Result<Self::Client> {
+        let incoming = self.0
+            .accept()
+            .await
+            .ok_or(io::ErrorKind::ConnectionAborted)?;
+        Ok(C(Lazy::new(async move {
+            let conn = incoming.await?;
+            let (send, mut recv) = conn.accept_bi().await?;
+            recv.read_exact(&mut [0]).await
+                .map_err(io::Error::other)?;
+            Ok(Stream { send, recv, conn, endpoint: None, closing: None })
+        })))
+    }
+}
+
+fn configs() -> Result<(ClientConfig, ServerConfig), Box<dyn std::error::Error>> {
+    let client = QuicClientConfig::try_from(Arc::clone(&tls::CONFIGS.client))?;
+    let server = QuicServerConfig::try_from(Arc::clone(&tls::CONFIGS.server))?;
+    let mut transport = TransportConfig::default();
+    transport.max_idle_timeout(Some(IDLE_TIMEOUT.try_into()?));
+    let transport = Arc::new(transport);
+    let mut client = ClientConfig::new(Arc::new(client));
+    client.transport_config(Arc::clone(&transport));
+    let mut server = ServerConfig::with_crypto(Arc::new(server));
+    server.transport_config(transport);
+    Ok((client, server))
+}
+
+async fn resolve(addr: &str) -> io::Result<SocketAddr> {
+    net::lookup_host(addr)
+        .await?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to"))
+}
@@ -1,6 +1,7 @@
 use crate::nodes;
 
 use std::io;
+use std::net::Shutdown;
 use std::pin::Pin;
 use std::task::{Poll, Context};
 
@@ -45,7 +46,10 @@ impl Write for C {
         cx: &mut Context<'_>
     ) -> Poll<io::Result<()>>
     {
-        Pin::new(&mut self.0).poll_close(cx)
+        // closing only flushes, so the peer would never read the end
+        // of the stream while we still hold the socket
+        futures::ready!(Pin::new(&mut self.0).poll_close(cx))?;
+        Poll::Ready(self.0.shutdown(Shutdown::Write))
     }
 }
 
@@ -0,0 +1,65 @@
+use crate::nodes;
+
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+
+use async_trait::async_trait;
+use tokio_uring::buf::BoundedBuf;
+use tokio_uring::net::{TcpListener, TcpStream};
+
+pub struct C(TcpStream);
+pub struct S(TcpListener);
+
+#[async_trait(?Send)]
+impl nodes::CompletionClient for C {
+    type Addr = &'static str;
+
+    async fn connect_server_completion(addr: Self::Addr) -> io::Result<Self> {
+        TcpStream::connect(resolve(addr)?)
+            .await
+            .map(C)
+    }
+
+    async fn read_exact_owned(&self, mut buf: Vec<u8>, len: usize) -> (io::Result<()>, Vec<u8>) {
+        buf.reserve(len.saturating_sub(buf.len()));
+        while buf.len() < len {
+            let from = buf.len();
+            let (result, slice) = self.0.read(buf.slice(from..len)).await;
+            buf = slice.into_inner();
+            match result {
+                Ok(0) => return (Err(io::ErrorKind::UnexpectedEof.into()), buf),
+                Ok(_) => (),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return (Err(e), buf),
+            }
+        }
+        (Ok(()), buf)
+    }
+
+    async fn write_all_owned(&self, buf: Vec<u8>) -> (io::Result<()>, Vec<u8>) {
+        self.0.write_all(buf).await
+    }
+}
+
+#[async_trait(?Send)]
+impl nodes::CompletionServer for S {
+    type Client = C;
+
+    async fn listen_clients_completion(addr: <<Self as nodes::CompletionServer>::Client as nodes::CompletionClient>::Addr) -> io::Result<Self> {
+        TcpListener::bind(resolve(addr)?).map(S)
+    }
+
+    async fn accept_client_completion(&self) -> io::Result<Self::Client> {
+        self.0
+            .accept()
+            .await
+            .map(|(c, _)| C(c))
+    }
+}
+
+// tokio-uring only takes socket addresses
+fn resolve(addr: &str) -> io::Result<SocketAddr> {
+    addr.to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to"))
+}
@@ -1,7 +1,7 @@
 use crate::nodes;
 
 use std::io;
-use std::net::{TcpListener, TcpStream};
+use std::net::{Shutdown, TcpListener, TcpStream};
 
 pub struct C(TcpStream);
 pub struct S(TcpListener);
@@ -12,6 +12,14 @@ impl nodes::Client for C {
     fn connect_server(addr: Self::Addr) -> io::Result<Self> {
         TcpStream::connect(addr).map(C)
     }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        self.0.try_clone().map(C)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        self.0.shutdown(Shutdown::Write)
+    }
 }
 
 impl io::Read for C {
@@ -0,0 +1,177 @@
+use crate::nodes;
+use crate::nodes::tls;
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+use rustls::{ClientConnection, Connection, ServerConnection};
+
+/// A TLS connection, which may be read on one thread while being
+/// written on another. The socket is only ever used without holding
+/// the lock on the TLS state, so a blocked read or write doesn't hold
+/// up the other direction. The handshake is driven by the first
+/// reads and writes, though writes only go so far ahead of it, and
+/// then wait for the reads to get it done.
+pub struct C {
+    shared: Arc<Shared>,
+    // read from the socket, but not handed to rustls yet
+    records: Box<[u8]>,
+    pending: Range<usize>,
+}
+
+pub struct S(TcpListener);
+
+struct Shared {
+    conn: Mutex<Connection>,
+    sock: TcpStream,
+    // held from sealing records until they are written,
+    // so they reach the socket in order
+    sending: Mutex<()>,
+    // signalled as the reads get the handshake done, or fail to
+    handshake: Condvar,
+    failed: AtomicBool,
+}
+
+impl C {
+    fn new(sock: TcpStream, conn: Connection) -> Self {
+        let shared = Shared {
+            conn: Mutex::new(conn),
+            sock,
+            sending: Mutex::new(()),
+            handshake: Condvar::new(),
+            failed: AtomicBool::new(false),
+        };
+        C::with(Arc::new(shared))
+    }
+
+    fn with(shared: Arc<Shared>) -> Self {
+        C { shared, records: vec![0; 16 * 1024].into_boxed_slice(), pending: 0..0 }
+    }
+
+    // write out whatever records are pending
+    fn send(&self, mut conn: MutexGuard<'_, Connection>) -> io::Result<()> {
+        let mut records = Vec::new();
+        while conn.wants_write() {
+            conn.write_tls(&mut records)?;
+        }
+        if records.is_empty() {
+            return Ok(());
+        }
+        let _sending = self.shared.sending.lock().unwrap();
+        drop(conn);
+        (&self.shared.sock).write_all(&records)
+    }
+
+    fn read_plaintext(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut conn = self.shared.conn.lock().unwrap();
+        loop {
+            match conn.reader().read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                result => return result,
+            }
+            if self.pending.is_empty() {
+                // e.g. our part of the handshake
+                self.send(conn)?;
+                let n = (&self.shared.sock).read(&mut self.records)?;
+                self.pending = 0..n;
+                conn = self.shared.conn.lock().unwrap();
+            }
+            // a little at a time, as rustls only holds so much
+            // plaintext; an empty read tells it the peer hung up
+            let mut records = &self.records[self.pending.clone()];
+            let n = conn.read_tls(&mut records)?;
+            self.pending.start += n;
+            let handshaking = conn.is_handshaking();
+            conn.process_new_packets()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            if handshaking && !conn.is_handshaking() {
+                self.shared.handshake.notify_all();
+            }
+            if conn.wants_write() {
+                self.send(conn)?;
+                conn = self.shared.conn.lock().unwrap();
+            }
+        }
+    }
+}
+
+impl nodes::Client for C {
+    type Addr = &'static str;
+
+    fn connect_server(addr: Self::Addr) -> io::Result<Self> {
+        let sock = TcpStream::connect(addr)?;
+        // handshake flights are written a record at a time
+        sock.set_nodelay(true)?;
+        let conn = ClientConnection::new(Arc::clone(&tls::CONFIGS.client), tls::server_name())
+            .map_err(io::Error::other)?;
+        Ok(C::new(sock, conn.into()))
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(C::with(Arc::clone(&self.shared)))
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        let mut conn = self.shared.conn.lock().unwrap();
+        conn.send_close_notify();
+        self.send(conn)?;
+        self.shared.sock.shutdown(Shutdown::Write)
+    }
+}
+
+impl io::Read for C {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.read_plaintext(buf);
+        if let Err(e) = &result {
+            if e.kind() != io::ErrorKind::Interrupted && !self.shared.failed.swap(true, Ordering::Relaxed) {
+                // so a write waiting on the handshake gives up; taking
+                // the lock, it either sees the failure or is waiting
+                drop(self.shared.conn.lock().unwrap());
+                self.shared.handshake.notify_all();
+            }
+        }
+        result
+    }
+}
+
+impl io::Write for C {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut conn = self.shared.conn.lock().unwrap();
+        let mut n = conn.writer().write(buf)?;
+        // rustls only holds so much plaintext until the handshake
+        // is done, and it's for the reads to finish it
+        while n == 0 && !buf.is_empty() && conn.is_handshaking() {
+            if self.shared.failed.load(Ordering::Relaxed) {
+                return Err(io::ErrorKind::ConnectionAborted.into());
+            }
+            conn = self.shared.handshake.wait(conn).unwrap();
+            n = conn.writer().write(buf)?;
+        }
+        self.send(conn)?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let conn = self.shared.conn.lock().unwrap();
+        self.send(conn)
+    }
+}
+
+impl nodes::Server for S {
+    type Client = C;
+
+    fn listen_clients(addr: <<Self as nodes::Server>::Client as nodes::Client>::Addr) -> io::Result<Self> {
+        TcpListener::bind(addr).map(S)
+    }
+
+    fn accept_client(&self) -> io::Result<Self::Client> {
+        let (sock, _) = self.0.accept()?;
+        sock.set_nodelay(true)?;
+        let conn = ServerConnection::new(Arc::clone(&tls::CONFIGS.server))
+            .map_err(io::Error::other)?;
+        Ok(C::new(sock, conn.into()))
+    }
+}
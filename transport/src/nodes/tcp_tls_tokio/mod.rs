@@ -0,0 +1,97 @@
+use crate::nodes;
+use crate::nodes::lazy::Lazy;
+use crate::nodes::tls;
+
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Poll, Context};
+
+use async_trait::async_trait;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::{TlsAcceptor, TlsConnector, TlsStream};
+use futures::io::{AsyncRead, AsyncWrite};
+use tokio_util::compat::{
+    Compat,
+    TokioAsyncReadCompatExt,
+};
+
+/// A TLS connection, whose handshake is driven by its first reads
+/// and writes.
+pub struct C(Lazy<Compat<TlsStream<TcpStream>>>);
+pub struct S(TcpListener);
+
+impl AsyncRead for C {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8]
+    ) -> Poll<io::Result<usize>>
+    {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for C {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8]
+    ) -> Poll<io::Result<usize>>
+    {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>
+    ) -> Poll<io::Result<()>>
+    {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>
+    ) -> Poll<io::Result<()>>
+    {
+        Pin::new(&mut self.0).poll_close(cx)
+    }
+}
+
+#[async_trait]
+impl nodes::AsyncClient for C {
+    type Addr = &'static str;
+
+    async fn connect_server_async(addr: Self::Addr) -> io::Result<Self> {
+        let sock = TcpStream::connect(addr).await?;
+        // handshake flights are written a record at a time
+        sock.set_nodelay(true)?;
+        let connector = TlsConnector::from(Arc::clone(&tls::CONFIGS.client));
+        let handshake = connector.connect(tls::server_name(), sock);
+        Ok(C(Lazy::new(async move {
+            handshake.await.map(|stream| TlsStream::from(stream).compat())
+        })))
+    }
+}
+
+#[async_trait]
+impl nodes::AsyncServer for S {
+    type Client = C;
+
+    async fn listen_clients_async(addr: <<Self as nodes::AsyncServer>::Client as nodes::AsyncClient>::Addr) -> io::Result<Self> {
+        TcpListener::bind(addr)
+            .await
+            .map(S)
+    }
+
+    async fn accept_client_async(&self) -> io::Result<Self::Client> {
+        let (sock, _) = self.0.accept().await?;
+        sock.set_nodelay(true)?;
+        let acceptor = TlsAcceptor::from(Arc::clone(&tls::CONFIGS.server));
+        let handshake = acceptor.accept(sock);
+        Ok(C(Lazy::new(async move {
+            handshake.await.map(|stream| TlsStream::from(stream).compat())
+        })))
+    }
+}
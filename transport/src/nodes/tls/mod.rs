@@ -0,0 +1,83 @@
+//! Certificates and configs of the TLS backends.
+//!
+//! Like our replica links, both ends authenticate each other with
+//! certificates issued by a common CA. Rather than shipping its files
+//! around, each process derives the CA key from the same fixed seed,
+//! and issues itself a fresh certificate with it. They prove nothing;
+//! they only make the handshake do the work it does in a deployment.
+
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use lazy_static::lazy_static;
+use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+
+/// The name the server's certificate is issued for, whatever
+/// its address.
+pub const SERVER_NAME: &str = "transport";
+
+/// Seed of the CA key, fixed on purpose, so that the two ends trust
+/// each other from separate processes, or machines, without sharing
+/// any files. Anyone with the source can sign certificates this CA
+/// vouches for: it must never be trusted outside this benchmark.
+const CA_SEED: [u8; 32] = *b"transport benchmark ca key seed!";
+
+pub struct Configs {
+    pub client: Arc<ClientConfig>,
+    pub server: Arc<ServerConfig>,
+}
+
+lazy_static! {
+    pub static ref CONFIGS: Configs = configs().expect("Failed to set up the certificates.");
+}
+
+pub fn server_name() -> ServerName<'static> {
+    ServerName::try_from(SERVER_NAME).unwrap()
+}
+
+fn configs() -> Result<Configs, Box<dyn std::error::Error>> {
+    let ca_key = KeyPair::from_pkcs8_der_and_sign_algo(&ed25519_pkcs8(&CA_SEED), &rcgen::PKCS_ED25519)?;
+    let mut ca_params = CertificateParams::new(Vec::<String>::new())?;
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    ca_params.distinguished_name.push(DnType::CommonName, "transport benchmark CA");
+    let ca = ca_params.self_signed(&ca_key)?;
+
+    // the same certificate serves us both as a client and as a server
+    let key = KeyPair::generate()?;
+    let cert = CertificateParams::new(vec![SERVER_NAME.to_string()])?
+        .signed_by(&key, &ca, &ca_key)?;
+    let chain: Vec<CertificateDer<'static>> = vec![cert.der().clone(), ca.der().clone()];
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der()));
+
+    let mut roots = RootCertStore::empty();
+    roots.add(ca.der().clone())?;
+    let roots = Arc::new(roots);
+    let provider = Arc::new(ring::default_provider());
+
+    let verifier = WebPkiClientVerifier::builder_with_provider(Arc::clone(&roots), Arc::clone(&provider)).build()?;
+    let server = ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()?
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(chain.clone(), key.clone_key())?;
+    let client = ClientConfig::builder_with_provider(provider as Arc<CryptoProvider>)
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_client_auth_cert(chain, key)?;
+
+    Ok(Configs { client: Arc::new(client), server: Arc::new(server) })
+}
+
+// a PKCS#8 v1 document holding the Ed25519 key of `seed`
+fn ed25519_pkcs8(seed: &[u8; 32]) -> PrivatePkcs8KeyDer<'static> {
+    const PREFIX: [u8; 16] = [
+        0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06,
+        0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+    ];
+    let mut der = PREFIX.to_vec();
+    der.extend_from_slice(seed);
+    PrivatePkcs8KeyDer::from(der)
+}
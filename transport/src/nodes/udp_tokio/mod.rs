@@ -0,0 +1,383 @@
+//! Datagrams over tokio sockets, with nothing to make up for the
+//! network: a lost or reordered datagram breaks the connection it
+//! belongs to, and is reported by the read that runs into it.
+//!
+//! Every datagram starts with a tag and a sequence number. A client
+//! says hello to open a connection, which the server answers in kind,
+//! and goodbye to close it; in between, what is written is cut into
+//! datagrams of at most `MAX_DATAGRAM` bytes. The server's clients
+//! share its socket, which a task reads from for all of them.
+
+use crate::nodes;
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Poll, Context};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::io::{AsyncRead, AsyncWrite};
+use tokio::net::{self as net, UdpSocket};
+use tokio::io::ReadBuf;
+use tokio::sync::{mpsc, Mutex};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::task::JoinHandle;
+use tokio::time::{self, Instant, Sleep};
+
+/// The largest payload of a UDP datagram over IPv4.
+pub const MAX_DATAGRAM: usize = 65507;
+
+/// How long a read waits for a datagram before giving up on the
+/// connection, as one that was lost would never arrive.
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// how long a client waits for an answer before saying hello again
+const HELLO_INTERVAL: Duration = Duration::from_millis(100);
+
+const HELLO: u8 = 0;
+const DATA: u8 = 1;
+const BYE: u8 = 2;
+
+// tag, then a big endian sequence number
+const HEADER: usize = 5;
+
+// datagrams queued for a client of the server before it starts
+// dropping them, like a socket's receive buffer
+const BACKLOG: usize = 1024;
+
+pub struct C {
+    io: Io,
+    // the last datagram read, of which `unread` is left
+    datagram: Vec<u8>,
+    unread: Range<usize>,
+    received: u32,
+    sent: u32,
+    outgoing: Vec<u8>,
+    eof: bool,
+    closed: bool,
+    idle: Pin<Box<Sleep>>,
+    waiting: bool,
+}
+
+enum Io {
+    // a client's own socket, connected to the server
+    Connected(UdpSocket),
+    // a client of the server, which hands over its datagrams
+    Routed {
+        sock: Arc<UdpSocket>,
+        peer: SocketAddr,
+        datagrams: mpsc::Receiver<Vec<u8>>,
+    },
+}
+
+pub struct S {
+    accepted: Mutex<mpsc::UnboundedReceiver<C>>,
+    router: JoinHandle<()>,
+}
+
+impl C {
+    fn new(io: Io) -> Self {
+        let datagram = match io {
+            Io::Connected(_) => vec![0; MAX_DATAGRAM],
+            Io::Routed { .. } => Vec::new(),
+        };
+        C {
+            io,
+            datagram,
+            unread: 0..0,
+            received: 0,
+            sent: 0,
+            outgoing: Vec::with_capacity(MAX_DATAGRAM),
+            eof: false,
+            closed: false,
+            idle: Box::pin(time::sleep(IDLE_TIMEOUT)),
+            waiting: false,
+        }
+    }
+
+    // the length of the next datagram, left in `datagram`
+    fn poll_datagram(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        match &mut self.io {
+            Io::Connected(sock) => {
+                let mut buf = ReadBuf::new(&mut self.datagram);
+                match sock.poll_recv(cx, &mut buf) {
+                    Poll::Ready(Ok(())) => Poll::Ready(Ok(buf.filled().len())),
+                    Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+                    Poll::Pending => Poll::Pending,
+                }
+            },
+            Io::Routed { datagrams, .. } => match datagrams.poll_recv(cx) {
+                Poll::Ready(Some(datagram)) => {
+                    self.datagram = datagram;
+                    Poll::Ready(Ok(self.datagram.len()))
+                },
+                Poll::Ready(None) => Poll::Ready(Err(io::ErrorKind::ConnectionAborted.into())),
+                Poll::Pending => Poll::Pending,
+            },
+        }
+    }
+
+    fn poll_idle(&mut self, cx: &mut Context<'_>) -> Poll<io::Error> {
+        if !self.waiting {
+            self.idle.as_mut().reset(Instant::now() + IDLE_TIMEOUT);
+            self.waiting = true;
+        }
+        match self.idle.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(io::Error::new(io::ErrorKind::TimedOut, "no datagrams from the peer")),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn process(&mut self, len: usize) -> io::Result<()> {
+        if len < HEADER {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "short datagram"));
+        }
+        let tag = self.datagram[0];
+        let seq = u32::from_be_bytes(self.datagram[1..HEADER].try_into().unwrap());
+        if tag == HELLO {
+            // e.g. the answer to our own
+            return Ok(());
+        }
+        if seq != self.received {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "lost or reordered datagrams"));
+        }
+        self.received = self.received.wrapping_add(1);
+        match tag {
+            DATA => self.unread = HEADER..len,
+            BYE => self.eof = true,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unknown datagram")),
+        }
+        Ok(())
+    }
+
+    // prepare the next datagram, with as much of `buf` as fits
+    fn seal(&mut self, tag: u8, buf: &[u8]) -> usize {
+        let n = buf.len().min(MAX_DATAGRAM - HEADER);
+        self.outgoing.clear();
+        self.outgoing.extend_from_slice(&header(tag, self.sent));
+        self.outgoing.extend_from_slice(&buf[..n]);
+        n
+    }
+
+    fn poll_send(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let sent = match &self.io {
+            Io::Connected(sock) => sock.poll_send(cx, &self.outgoing),
+            Io::Routed { sock, peer, .. } => sock.poll_send_to(cx, &self.outgoing, *peer),
+        };
+        match sent {
+            Poll::Ready(Ok(_)) => {
+                self.sent = self.sent.wrapping_add(1);
+                Poll::Ready(Ok(()))
+            },
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for C {
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+        // at least try to let the peer know
+        self.seal(BYE, &[]);
+        let _ = match &self.io {
+            Io::Connected(sock) => sock.try_send(&self.outgoing),
+            Io::Routed { sock, peer, .. } => sock.try_send_to(&self.outgoing, *peer),
+        };
+    }
+}
+
+impl AsyncRead for C {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8]
+    ) -> Poll<io::Result<usize>>
+    {
+        let this = self.get_mut();
+        loop {
+            if !this.unread.is_empty() {
+                let n = buf.len().min(this.unread.len());
+                let start = this.unread.start;
+                buf[..n].copy_from_slice(&this.datagram[start..start + n]);
+                this.unread.start += n;
+                return Poll::Ready(Ok(n));
+            }
+            if this.eof {
+                return Poll::Ready(Ok(0));
+            }
+            let len = match this.poll_datagram(cx) {
+                Poll::Ready(Ok(len)) => len,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return match this.poll_idle(cx) {
+                    Poll::Ready(e) => Poll::Ready(Err(e)),
+                    Poll::Pending => Poll::Pending,
+                },
+            };
+            this.waiting = false;
+            this.process(len)?;
+        }
+    }
+}
+
+impl AsyncWrite for C {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8]
+    ) -> Poll<io::Result<usize>>
+    {
+        let this = self.get_mut();
+        if this.closed {
+            return Poll::Ready(Err(io::ErrorKind::NotConnected.into()));
+        }
+        let n = this.seal(DATA, buf);
+        match this.poll_send(cx) {
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(n)),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>
+    ) -> Poll<io::Result<()>>
+    {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>
+    ) -> Poll<io::Result<()>>
+    {
+        let this = self.get_mut();
+        if this.closed {
+            return Poll::Ready(Ok(()));
+        }
+        this.seal(BYE, &[]);
+        match this.poll_send(cx) {
+            Poll::Ready(Ok(())) => {
+                this.closed = true;
+                Poll::Ready(Ok(()))
+            },
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[async_trait]
+impl nodes::AsyncClient for C {
+    type Addr = &'static str;
+
+    async fn connect_server_async(addr: Self::Addr) -> io::Result<Self> {
+        let addr = resolve(addr).await?;
+        let local = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let sock = UdpSocket::bind(local).await?;
+        sock.connect(addr).await?;
+        // wait for the server to answer, as a TCP handshake would
+        let deadline = Instant::now() + IDLE_TIMEOUT;
+        let mut answer = [0; HEADER];
+        loop {
+            sock.send(&header(HELLO, 0)).await?;
+            match time::timeout(HELLO_INTERVAL, sock.recv(&mut answer)).await {
+                Ok(Ok(HEADER)) if answer[0] == HELLO => return Ok(C::new(Io::Connected(sock))),
+                Ok(Ok(_)) => return Err(io::Error::new(io::ErrorKind::InvalidData, "unexpected answer to hello")),
+                Ok(Err(e)) => return Err(e),
+                // either hello may be lost, and the server ignores repeats
+                Err(_) if Instant::now() < deadline => continue,
+                Err(_) => return Err(io::Error::new(io::ErrorKind::TimedOut, "no answer to hello")),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl nodes::AsyncServer for S {
+    type Client = C;
+
+    async fn listen_clients_async(addr: <<Self as nodes::AsyncServer>::Client as nodes::AsyncClient>::Addr) -> io::Result<Self> {
+        let sock = UdpSocket::bind(addr).await?;
+        let (tx, rx) = mpsc::unbounded_channel();
+        let router = tokio::spawn(route(Arc::new(sock), tx));
+        Ok(S { accepted: Mutex::new(rx), router })
+    }
+
+    async fn accept_client_async(&self) -> io::Result<Self::Client> {
+        self.accepted
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| io::ErrorKind::ConnectionAborted.into())
+    }
+}
+
+impl Drop for S {
+    fn drop(&mut self) {
+        self.router.abort();
+    }
+}
+
+// hand the datagrams of the server's socket to the clients they are from,
+// and those saying hello to whoever accepts them
+async fn route(sock: Arc<UdpSocket>, accepted: mpsc::UnboundedSender<C>) {
+    let mut peers: HashMap<SocketAddr, mpsc::Sender<Vec<u8>>> = HashMap::new();
+    let mut datagram = vec![0; MAX_DATAGRAM];
+    loop {
+        let (len, peer) = match sock.recv_from(&mut datagram).await {
+            Ok(received) => received,
+            Err(_) => continue,
+        };
+        match datagram[..len].first() {
+            Some(&HELLO) => {
+                let _ = sock.send_to(&header(HELLO, 0), peer).await;
+                // a repeat, unless the client before it on the same
+                // address is gone, as it may not have said goodbye
+                if peers.get(&peer).is_some_and(|tx| !tx.is_closed()) {
+                    continue;
+                }
+                let (tx, rx) = mpsc::channel(BACKLOG);
+                let c = C::new(Io::Routed { sock: Arc::clone(&sock), peer, datagrams: rx });
+                if accepted.send(c).is_err() {
+                    return;
+                }
+                peers.insert(peer, tx);
+            },
+            Some(&tag) => {
+                let gone = match peers.get(&peer).map(|tx| tx.try_send(datagram[..len].to_vec())) {
+                    // dropped when full, like the kernel would
+                    Some(Ok(())) | Some(Err(TrySendError::Full(_))) => tag == BYE,
+                    Some(Err(TrySendError::Closed(_))) => true,
+                    None => false,
+                };
+                if gone {
+                    peers.remove(&peer);
+                }
+            },
+            None => (),
+        }
+    }
+}
+
+async fn resolve(addr: &str) -> io::Result<SocketAddr> {
+    net::lookup_host(addr)
+        .await?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to"))
+}
+
+fn header(tag: u8, seq: u32) -> [u8; HEADER] {
+    let seq = seq.to_be_bytes();
+    [tag, seq[0], seq[1], seq[2], seq[3]]
+}
@@ -0,0 +1,68 @@
+use crate::nodes;
+
+use std::fs;
+use std::io;
+use std::net::Shutdown;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+
+/// A Unix domain socket, whose address is a path on the file system.
+pub struct C(UnixStream);
+pub struct S(UnixListener);
+
+impl nodes::Client for C {
+    type Addr = &'static str;
+
+    fn connect_server(addr: Self::Addr) -> io::Result<Self> {
+        UnixStream::connect(addr).map(C)
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        self.0.try_clone().map(C)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        self.0.shutdown(Shutdown::Write)
+    }
+}
+
+impl io::Read for C {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl io::Write for C {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl nodes::Server for S {
+    type Client = C;
+
+    fn listen_clients(addr: <<Self as nodes::Server>::Client as nodes::Client>::Addr) -> io::Result<Self> {
+        remove_stale(addr)?;
+        UnixListener::bind(addr).map(S)
+    }
+
+    fn accept_client(&self) -> io::Result<Self::Client> {
+        self.0.accept().map(|(client, _)| C(client))
+    }
+}
+
+/// Remove the socket a previous server left behind at `path`, as
+/// binding to it fails otherwise. Anything but a socket is left alone.
+pub(crate) fn remove_stale<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    match fs::symlink_metadata(&path) {
+        Ok(meta) if meta.file_type().is_socket() => fs::remove_file(path),
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
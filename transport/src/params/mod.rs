@@ -1,20 +1,250 @@
+use std::convert::TryFrom;
+use std::env;
+use std::io;
+use std::net::{TcpListener, UdpSocket};
+use std::process;
 use std::time::Duration;
-use konst::{
-    primitive::parse_usize,
-    unwrap_ctx,
-};
-
-// 2 minutes per test case
-pub const SECS: u64 = 5;
-pub const TIME: Duration = Duration::from_secs(SECS);
-
-pub const BUFSIZ: usize = {
-    let result = parse_usize(env!("BUFSIZ"));
-    unwrap_ctx!(result)
-};
-pub const LADDR: &str = "0.0.0.0:12345";
-pub const N1: &str = "192.168.70.16:12345";
-pub const N2: &str = "192.168.70.17:12345";
-pub const N3: &str = "192.168.70.18:12345";
-pub const N4: &str = "192.168.70.19:12345";
-pub const ADDRS: [&str; 4] = [N2, N1, N3, N4];
+
+use clap::{Parser, ValueEnum};
+use clap::builder::RangedU64ValueParser;
+
+/// Measure the cost of messaging between two machines, with
+/// different transports and runtimes.
+#[derive(Debug, Clone, Parser)]
+pub struct Params {
+    /// The transport, and what drives it.
+    #[arg(value_enum)]
+    pub backend: Backend,
+    /// Which end of the connection to run; both, for the nodes
+    /// of test 3. A local run has every end in this process, over
+    /// loopback, on addresses picked for it.
+    #[arg(value_enum)]
+    pub role: Role,
+    /// 1: request/response over one connection, 2: a new connection
+    /// per request, 3: every node broadcasting to all the others.
+    #[arg(short, long, env = "TEST", value_parser = RangedU64ValueParser::<u8>::new().range(1..=3))]
+    pub test: u8,
+    /// Address the server listens on; a path for the Unix
+    /// domain socket backends.
+    #[arg(long, default_value = "0.0.0.0:12345")]
+    pub listen: String,
+    /// Address of the server, for clients; a path for the Unix
+    /// domain socket backends.
+    #[arg(long, default_value = "127.0.0.1:12345")]
+    pub server: String,
+    /// Payload sizes in bytes, measured one after the other,
+    /// e.g. 8,512,4096.
+    #[arg(short, long, env = "BUFSIZ", value_delimiter = ',', default_value = "8")]
+    pub bufsiz: Vec<usize>,
+    /// Seconds to measure each payload size for.
+    #[arg(short, long = "duration", value_name = "SECS", default_value_t = 5)]
+    pub duration_secs: u64,
+    /// Seconds to run each payload size for before measuring it.
+    #[arg(short, long = "warmup", value_name = "SECS", default_value_t = 0)]
+    pub warmup_secs: u64,
+    /// The runtime driving an async backend; its own by default.
+    /// Those of async-io sockets, i.e. tcp:async_std, may run on any,
+    /// and those of tokio sockets on either of tokio's.
+    #[arg(long, env = "RUNTIME", value_enum)]
+    pub runtime: Option<RuntimeKind>,
+    /// Worker threads of the async runtimes, but for tokio's current
+    /// thread one, and io_uring's.
+    #[arg(long, env = "THREADS", default_value_t = 4, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub threads: usize,
+    /// Requests a test 1 client keeps in flight.
+    #[arg(long, env = "DEPTH", default_value_t = 1, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub depth: usize,
+    /// Nodes of test 3, each connected to all the others.
+    #[arg(long, env = "NODES", default_value_t = 4, value_parser = RangedU64ValueParser::<usize>::new().range(2..))]
+    pub nodes: usize,
+    /// The nodes of test 3 this process runs, by id; all of them by
+    /// default. Node i listens on --listen, and is reached at --server,
+    /// with i added to the port, or to the end of the path.
+    #[arg(long = "node", value_name = "IDS", value_delimiter = ',')]
+    pub node_ids: Vec<usize>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    /// Blocking sockets, a thread per connection.
+    #[value(name = "tcp:sync")]
+    TcpSync,
+    /// Tokio sockets, on its multi-threaded runtime.
+    #[value(name = "tcp:tokio")]
+    TcpTokio,
+    /// async-std sockets, on its global executor.
+    #[value(name = "tcp:async_std")]
+    TcpAsyncStd,
+    /// tokio-uring sockets, completing on one thread's ring.
+    #[value(name = "tcp:io_uring")]
+    TcpIoUring,
+    /// rustls over blocking sockets, with a certificate on each end.
+    #[value(name = "tls:sync")]
+    TcpTlsSync,
+    /// tokio-rustls over tokio sockets, with a certificate on each end.
+    #[value(name = "tls:tokio")]
+    TcpTlsTokio,
+    /// Datagrams over tokio sockets, with no retransmissions.
+    #[value(name = "udp:tokio")]
+    UdpTokio,
+    /// quinn over tokio sockets, with a certificate on each end.
+    #[value(name = "quic:tokio")]
+    Quic,
+    /// Unix domain sockets, blocking, a thread per connection.
+    #[value(name = "uds:sync")]
+    UdsSync,
+    /// Unix domain sockets, on tokio's multi-threaded runtime.
+    #[value(name = "uds:tokio")]
+    UdsTokio,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum RuntimeKind {
+    /// Tokio's multi-threaded, work-stealing scheduler.
+    #[value(name = "tokio")]
+    Tokio,
+    /// Tokio's scheduler, on the thread blocking on the test.
+    #[value(name = "tokio:current_thread")]
+    TokioCurrentThread,
+    /// async-std's global executor.
+    #[value(name = "async_std")]
+    AsyncStd,
+    /// A smol executor.
+    #[value(name = "smol")]
+    Smol,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Role {
+    Client,
+    Server,
+    Node,
+    Local,
+}
+
+/// The nodes of test 3: where each of them listens, and is reached,
+/// by id, and which of them this process runs.
+pub struct Mesh {
+    pub listen: Vec<String>,
+    pub connect: Vec<String>,
+    pub own: Vec<usize>,
+}
+
+impl Backend {
+    /// The runtimes the sockets of the backend work with, its own
+    /// first; none for those that run without one.
+    pub fn runtimes(self) -> &'static [RuntimeKind] {
+        match self {
+            Backend::TcpTokio
+            | Backend::TcpTlsTokio
+            | Backend::UdpTokio
+            | Backend::Quic
+            | Backend::UdsTokio => &[RuntimeKind::Tokio, RuntimeKind::TokioCurrentThread],
+            Backend::TcpAsyncStd => &[
+                RuntimeKind::AsyncStd,
+                RuntimeKind::Tokio,
+                RuntimeKind::TokioCurrentThread,
+                RuntimeKind::Smol,
+            ],
+            _ => &[],
+        }
+    }
+
+    pub fn is_unix(self) -> bool {
+        matches!(self, Backend::UdsSync | Backend::UdsTokio)
+    }
+
+    /// Addresses for `n` servers over loopback, which no one else
+    /// listens on for now.
+    pub fn loopback(self, n: usize) -> io::Result<Vec<String>> {
+        if self.is_unix() {
+            let base = env::temp_dir().join(format!("transport-{}", process::id()));
+            return Ok((0..n).map(|id| format!("{}.{}", base.display(), id)).collect());
+        }
+        // the ports are all held until picked, so they differ
+        let addrs = if matches!(self, Backend::UdpTokio | Backend::Quic) {
+            (0..n)
+                .map(|_| UdpSocket::bind("127.0.0.1:0"))
+                .collect::<io::Result<Vec<_>>>()?
+                .iter()
+                .map(UdpSocket::local_addr)
+                .collect::<io::Result<Vec<_>>>()?
+        } else {
+            (0..n)
+                .map(|_| TcpListener::bind("127.0.0.1:0"))
+                .collect::<io::Result<Vec<_>>>()?
+                .iter()
+                .map(TcpListener::local_addr)
+                .collect::<io::Result<Vec<_>>>()?
+        };
+        Ok(addrs.iter().map(ToString::to_string).collect())
+    }
+}
+
+impl Mesh {
+    /// Every node, run by this process, on `addrs`.
+    pub fn local(addrs: Vec<String>) -> Self {
+        Mesh {
+            listen: addrs.clone(),
+            connect: addrs.clone(),
+            own: (0..addrs.len()).collect(),
+        }
+    }
+}
+
+impl Params {
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs(self.duration_secs)
+    }
+
+    pub fn warmup(&self) -> Duration {
+        Duration::from_secs(self.warmup_secs)
+    }
+
+    /// The runtime to drive the backend with.
+    pub fn runtime(&self) -> Result<RuntimeKind, String> {
+        let runtimes = self.backend.runtimes();
+        match self.runtime.or_else(|| runtimes.first().copied()) {
+            Some(runtime) if runtimes.contains(&runtime) => Ok(runtime),
+            Some(runtime) => Err(format!("{} doesn't run on {}.", name(self.backend), name(runtime))),
+            None => Err(format!("{} runs on no async runtime.", name(self.backend))),
+        }
+    }
+
+    pub fn mesh(&self) -> Result<Mesh, String> {
+        let own = if self.node_ids.is_empty() {
+            (0..self.nodes).collect()
+        } else {
+            self.node_ids.clone()
+        };
+        if let Some(id) = own.iter().find(|&&id| id >= self.nodes) {
+            return Err(format!("There is no node {} of {}.", id, self.nodes));
+        }
+        Ok(Mesh {
+            listen: (0..self.nodes).map(|id| node_addr(&self.listen, id)).collect::<Result<_, _>>()?,
+            connect: (0..self.nodes).map(|id| node_addr(&self.server, id)).collect::<Result<_, _>>()?,
+            own,
+        })
+    }
+}
+
+// a port moves up by the id of the node, and a path gets it appended
+fn node_addr(base: &str, id: usize) -> Result<String, String> {
+    let port = base
+        .rsplit_once(':')
+        .filter(|(_, port)| !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()));
+    match port {
+        Some((host, port)) => port
+            .parse::<u16>()
+            .ok()
+            .zip(u16::try_from(id).ok())
+            .and_then(|(port, id)| port.checked_add(id))
+            .map(|port| format!("{}:{}", host, port))
+            .ok_or_else(|| format!("The port of {} is out of range for node {}.", base, id)),
+        None => Ok(format!("{}.{}", base, id)),
+    }
+}
+
+fn name<V: ValueEnum>(value: V) -> String {
+    value.to_possible_value().unwrap().get_name().to_string()
+}
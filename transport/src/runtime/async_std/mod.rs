@@ -5,18 +5,14 @@ use async_std::task;
 
 pub struct Runtime;
 
-impl Runtime {
-    pub fn init() {
-        match std::env::var("THREADS").as_ref() {
-            Ok(ref n) => std::env::set_var("ASYNC_STD_THREAD_COUNT", &n),
-            _ => (),
-        }
-    }
-}
-
 impl super::Runtime for Runtime {
     type Task = task::JoinHandle<Option<TaskOutput>>;
 
+    fn init(threads: usize) {
+        // read when the global executor first starts
+        std::env::set_var("ASYNC_STD_THREAD_COUNT", threads.to_string());
+    }
+
     fn block_on<F: Future>(fut: F) -> F::Output {
         task::block_on(fut)
     }
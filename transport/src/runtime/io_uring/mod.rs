@@ -0,0 +1,28 @@
+use std::future::Future;
+
+use super::TaskOutput;
+use super::tokio::Task;
+
+/// A tokio-uring runtime: a single thread, submitting to and
+/// reaping from its own ring. Tasks may not leave it, as the
+/// buffers of their operations are tied to that ring.
+pub struct Runtime;
+
+impl super::LocalRuntime for Runtime {
+    type Task = Task<Option<TaskOutput>>;
+
+    fn init(_threads: usize) {
+        // there is one thread per ring, whatever is asked
+    }
+
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        tokio_uring::start(fut)
+    }
+
+    fn spawn_local<F>(fut: F) -> Self::Task
+    where
+        F: 'static + Future<Output = Option<TaskOutput>>,
+    {
+        Task(tokio_uring::spawn(fut))
+    }
+}
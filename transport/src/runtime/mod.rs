@@ -2,13 +2,31 @@ use std::future::Future;
 
 pub mod tokio;
 pub mod async_std;
+pub mod smol;
+pub mod io_uring;
 
 pub type TaskOutput = u64;
 
-// should use THREADS env var to configure thread pool
 pub trait Runtime {
-    type Task: Future<Output = Option<TaskOutput>>;
+    /// A handle to a spawned task, which may be awaited from
+    /// another task.
+    type Task: Send + Future<Output = Option<TaskOutput>>;
+
+    /// Size the thread pool; called before anything else.
+    fn init(threads: usize);
 
     fn block_on<F: Future>(fut: F) -> F::Output;
     fn spawn<F: 'static + Send + Future<Output = Option<TaskOutput>>>(fut: F) -> Self::Task;
 }
+
+/// A runtime whose tasks stay on the thread that spawned them, as
+/// those doing completion-based I/O must.
+pub trait LocalRuntime {
+    type Task: Future<Output = Option<TaskOutput>>;
+
+    /// Size the thread pool; called before anything else.
+    fn init(threads: usize);
+
+    fn block_on<F: Future>(fut: F) -> F::Output;
+    fn spawn_local<F: 'static + Future<Output = Option<TaskOutput>>>(fut: F) -> Self::Task;
+}
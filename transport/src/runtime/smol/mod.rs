@@ -0,0 +1,68 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::thread;
+
+use super::TaskOutput;
+use lazy_static::lazy_static;
+use smol::Executor;
+
+/// A smol executor, run by a pool of worker threads, each of which
+/// may also drive the async-io reactor while it has nothing to do.
+pub struct Runtime;
+
+/// A handle to a task, which keeps running once the handle is
+/// dropped, as with the other runtimes.
+pub struct Task<T>(Option<smol::Task<T>>);
+
+static THREADS: AtomicUsize = AtomicUsize::new(4);
+
+lazy_static! {
+    static ref INSTANCE: &'static Executor<'static> = {
+        let executor: &'static Executor<'static> = Box::leak(Box::new(Executor::new()));
+        for _ in 0..THREADS.load(Ordering::Relaxed) {
+            thread::Builder::new()
+                .name("smol-worker".into())
+                .spawn(move || smol::block_on(executor.run(smol::future::pending::<()>())))
+                .unwrap();
+        }
+        executor
+    };
+}
+
+impl super::Runtime for Runtime {
+    type Task = Task<Option<TaskOutput>>;
+
+    fn init(threads: usize) {
+        THREADS.store(threads, Ordering::Relaxed);
+    }
+
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        smol::block_on(fut)
+    }
+
+    fn spawn<F>(fut: F) -> Self::Task
+    where
+        F: 'static + Send + Future<Output = Option<TaskOutput>>,
+    {
+        Task(Some(INSTANCE.spawn(fut)))
+    }
+}
+
+impl<T> Future for Task<T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(self.0.as_mut().unwrap()).poll(cx)
+    }
+}
+
+impl<T> Drop for Task<T> {
+    fn drop(&mut self) {
+        // smol cancels a task along with its last handle
+        if let Some(task) = self.0.take() {
+            task.detach();
+        }
+    }
+}
@@ -1,33 +1,44 @@
 use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use super::TaskOutput;
 use lazy_static::lazy_static;
 
 pub struct Runtime;
 
-pub struct Task<T>(::tokio::task::JoinHandle<T>);
+/// Tokio's current-thread runtime: tasks run on whichever thread is
+/// blocking on a future of it, one thread at a time.
+pub struct CurrentThread;
+
+pub struct Task<T>(pub(super) ::tokio::task::JoinHandle<T>);
+
+static THREADS: AtomicUsize = AtomicUsize::new(4);
 
 lazy_static! {
     static ref INSTANCE: ::tokio::runtime::Runtime = {
-        let num_threads = std::env::var("THREADS")
-            .as_ref()
-            .map(String::as_ref)
-            .unwrap_or("4")
-            .parse()
-            .unwrap_or(4);
         ::tokio::runtime::Builder::new_multi_thread()
-            .worker_threads(num_threads)
+            .worker_threads(THREADS.load(Ordering::Relaxed))
             .thread_name("tokio-worker")
             .thread_stack_size(2 * 1024 * 1024)
             .enable_all()
             .build()
             .unwrap()
     };
+    static ref CURRENT_THREAD: ::tokio::runtime::Runtime = {
+        ::tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    };
 }
 
 impl super::Runtime for Runtime {
     type Task = Task<Option<TaskOutput>>;
 
+    fn init(threads: usize) {
+        THREADS.store(threads, Ordering::Relaxed);
+    }
+
     fn block_on<F: Future>(fut: F) -> F::Output {
         INSTANCE.block_on(fut)
     }
@@ -40,6 +51,25 @@ impl super::Runtime for Runtime {
     }
 }
 
+impl super::Runtime for CurrentThread {
+    type Task = Task<Option<TaskOutput>>;
+
+    fn init(_threads: usize) {
+        // there are no workers, whatever is asked
+    }
+
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        CURRENT_THREAD.block_on(fut)
+    }
+
+    fn spawn<F>(fut: F) -> Self::Task
+    where
+        F: 'static + Send + Future<Output = Option<TaskOutput>>,
+    {
+        Task(CURRENT_THREAD.spawn(fut))
+    }
+}
+
 impl<T> Future for Task<T> {
     type Output = T;
 
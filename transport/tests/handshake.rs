@@ -0,0 +1,63 @@
+//! Backends that set their streams up on first use, against a peer
+//! that doesn't speak their protocol.
+
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// well over what a run takes, for a backend that hangs
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+// a peer answering whatever it is sent with an HTTP error
+fn not_tls() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for conn in listener.incoming() {
+            let mut conn = conn.unwrap();
+            let _ = conn.read(&mut [0; 512]);
+            let _ = conn.write_all(b"HTTP/1.0 400 Bad Request\r\n\r\n");
+        }
+    });
+    port
+}
+
+fn fails(backend: &str, args: &[&str]) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_transport"))
+        .arg(backend)
+        .args(args)
+        .args(["--duration", "1"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start the benchmark.");
+    let deadline = Instant::now() + TIMEOUT;
+    while child.try_wait().unwrap().is_none() {
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            panic!("{} {:?} took over {:?}", backend, args, TIMEOUT);
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    let output = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // an error, rather than a panic
+    assert_eq!(output.status.code(), Some(1), "{} {:?}: {}", backend, args, stderr);
+    assert!(!stderr.contains("panicked"), "{} {:?}: {}", backend, args, stderr);
+}
+
+#[test]
+fn test_tls_tokio_client() {
+    let server = format!("127.0.0.1:{}", not_tls());
+    fails("tls:tokio", &["client", "--test", "1", "--server", &server]);
+}
+
+#[test]
+fn test_tls_tokio_node() {
+    // both halves of the stream to node 1 see its handshake fail
+    let server = format!("127.0.0.1:{}", not_tls() - 1);
+    let args = ["node", "--test", "3", "--nodes", "2", "--node", "0"];
+    fails("tls:tokio", &[&args[..], &["--listen", "127.0.0.1:0", "--server", &server]].concat());
+}
@@ -0,0 +1,87 @@
+//! Every backend through every test, with both ends of it in one
+//! process, over loopback, and some on runtimes other than their own.
+
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SIZES: &str = "8,4096";
+const NODES: usize = 3;
+
+// well over what a run takes, for a backend that hangs
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+fn local(backend: &str, runtime: Option<&str>, test: u8) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_transport"))
+        .args([backend, "local"])
+        .args(runtime.map(|runtime| ["--runtime", runtime]).into_iter().flatten())
+        .args(["--test", &test.to_string()])
+        .args(["--bufsiz", SIZES, "--duration", "1"])
+        .args(["--nodes", &NODES.to_string()])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start the benchmark.");
+    let deadline = Instant::now() + TIMEOUT;
+    while child.try_wait().unwrap().is_none() {
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            panic!("{} on {:?} took over {:?} through test {}", backend, runtime, TIMEOUT, test);
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{} on {:?} failed test {}: {}",
+        backend,
+        runtime,
+        test,
+        String::from_utf8_lossy(&output.stderr),
+    );
+    // a line per payload size, and per node on test 3
+    let nodes = if test == 3 { NODES } else { 1 };
+    let sizes = SIZES.split(',').count();
+    assert_eq!(stdout.lines().count(), sizes * nodes, "{}", stdout);
+}
+
+macro_rules! backend {
+    ($name:ident, $backend:literal) => {
+        backend!($name, $backend, None);
+    };
+    ($name:ident, $backend:literal, $runtime:expr) => {
+        mod $name {
+            #[test]
+            fn test1() {
+                super::local($backend, $runtime, 1);
+            }
+
+            #[test]
+            fn test2() {
+                super::local($backend, $runtime, 2);
+            }
+
+            #[test]
+            fn test3() {
+                super::local($backend, $runtime, 3);
+            }
+        }
+    };
+}
+
+backend!(tcp_sync, "tcp:sync");
+backend!(tcp_tokio, "tcp:tokio");
+backend!(tcp_async_std, "tcp:async_std");
+backend!(tcp_io_uring, "tcp:io_uring");
+backend!(tls_sync, "tls:sync");
+backend!(tls_tokio, "tls:tokio");
+backend!(udp_tokio, "udp:tokio");
+backend!(quic_tokio, "quic:tokio");
+backend!(uds_sync, "uds:sync");
+backend!(uds_tokio, "uds:tokio");
+backend!(tcp_async_std_on_tokio, "tcp:async_std", Some("tokio"));
+backend!(tcp_async_std_on_tokio_current_thread, "tcp:async_std", Some("tokio:current_thread"));
+backend!(tcp_async_std_on_smol, "tcp:async_std", Some("smol"));
+backend!(tcp_tokio_on_tokio_current_thread, "tcp:tokio", Some("tokio:current_thread"));
+backend!(quic_tokio_on_tokio_current_thread, "quic:tokio", Some("tokio:current_thread"));